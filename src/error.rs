@@ -0,0 +1,146 @@
+use std::fmt;
+
+use position::*;
+
+type Name = String;
+
+// number of calls shown at each end of a stack trace
+const TRACE_ENDS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: &'static str,
+    pub pos: Position
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeErrorType {
+    Undeclared(Name),
+    AlreadyDeclared(Name),
+    TypeMismatch(String),
+    ArityMismatch(usize, usize),
+    // named argument without a parameter of that name
+    UnknownArgument(Name),
+    // named argument for a parameter that already got a value
+    DuplicateArgument(Name),
+    IndexOutOfRange(i64, usize),
+    IntegerOverflow,
+    DivisionByZero,
+    MissingKey(String),
+    AssertionFailed(String),
+    // the script calls that were running, the outermost first
+    CallDepthExceeded(Vec<(Name, Position)>),
+    // the native stack ran out, with the script calls that were running
+    StackExhausted(Vec<(Name, Position)>),
+    // the value no match arm accepted
+    NoMatch(String),
+    Unsupported(String)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub error_type: RuntimeErrorType,
+    pub pos: Position
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Parse(Vec<ParseError>),
+    Runtime(RuntimeError)
+}
+
+impl RuntimeErrorType {
+    pub fn with_pos(self, pos: Position) -> RuntimeError {
+        RuntimeError {
+            error_type: self,
+            pos
+        }
+    }
+}
+
+impl RuntimeError {
+    pub fn position(&self) -> &Position {
+        &self.pos
+    }
+}
+
+impl ParseError {
+    pub fn position(&self) -> &Position {
+        &self.pos
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Error {
+        Error::Runtime(err)
+    }
+}
+
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.message, self.pos)
+    }
+}
+
+// recursion makes for long traces, only both ends are interesting
+fn write_trace(f: &mut fmt::Formatter, trace: &[(Name, Position)]) -> fmt::Result {
+    writeln!(f, "script stack trace (most recent call last):")?;
+    for (i, (name, pos)) in trace.iter().enumerate() {
+        if i < TRACE_ENDS || i + TRACE_ENDS >= trace.len() {
+            writeln!(f, "  {} {}", name, pos)?;
+        } else if i == TRACE_ENDS {
+            writeln!(f, "  ... {} more calls", trace.len() - 2 * TRACE_ENDS)?;
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for RuntimeErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RuntimeErrorType::Undeclared(ref name) => write!(f, "\"{}\" was not declared", name),
+            RuntimeErrorType::AlreadyDeclared(ref name) => write!(f, "\"{}\" has already been declared", name),
+            RuntimeErrorType::TypeMismatch(ref msg) => write!(f, "{}", msg),
+            RuntimeErrorType::ArityMismatch(expected, got) => write!(f, "invalid number of arguments: expected {}, got {}", expected, got),
+            RuntimeErrorType::UnknownArgument(ref name) => write!(f, "no parameter named \"{}\"", name),
+            RuntimeErrorType::DuplicateArgument(ref name) => write!(f, "parameter \"{}\" was given more than once", name),
+            RuntimeErrorType::IndexOutOfRange(index, len) => write!(f, "index {} is out of range for length {}", index, len),
+            RuntimeErrorType::IntegerOverflow => write!(f, "integer overflow"),
+            RuntimeErrorType::DivisionByZero => write!(f, "division by zero"),
+            RuntimeErrorType::MissingKey(ref key) => write!(f, "key {} not found", key),
+            RuntimeErrorType::AssertionFailed(ref msg) => write!(f, "assertion failed: {}", msg),
+            RuntimeErrorType::NoMatch(ref val) => write!(f, "no arm matched {}", val),
+            RuntimeErrorType::CallDepthExceeded(ref trace) => {
+                write_trace(f, trace)?;
+                write!(f, "maximum call depth of {} exceeded", trace.len() - 1)
+            },
+            RuntimeErrorType::StackExhausted(ref trace) => {
+                write_trace(f, trace)?;
+                write!(f, "evaluation nested too deeply")
+            },
+            RuntimeErrorType::Unsupported(ref tree) => write!(f, "\"{}\" not supported", tree)
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.error_type, self.pos)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse(ref errs) => {
+                let mut r = write!(f, "{} errors:", errs.len());
+                for e in errs {
+                    r = r.and_then(|_| write!(f, "\n{}", e));
+                }
+                r
+            },
+            Error::Runtime(ref err) => write!(f, "{}", err)
+        }
+    }
+}
@@ -1,110 +1,863 @@
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::cmp::Ordering;
+use std::iter;
+use std::hint;
+use std::panic;
+use std::thread;
+use std::mem;
 
 use tree::*;
+use position::*;
 use value::*;
+use error::*;
+use map::*;
 
 type Name = String;
 
+// the indices leading to an element, with the position of each indexing
+type Path = Vec<(Value, Position)>;
+
+// a single frame, lookups that fail here continue in the parent frame
 #[derive(Debug)]
+struct Scope {
+    values: RefCell<HashMap<Name, Value>>,
+    parent: Option<Rc<Scope>>,
+    // how many of the values are functions capturing this frame, see release
+    self_refs: Cell<usize>
+}
+
+impl Scope {
+    fn captured_by(self: &Rc<Scope>, val: &Value) -> bool {
+        match *val {
+            Value::Func(_, _, ref env) => Rc::ptr_eq(&env.scope, self),
+            _ => false
+        }
+    }
+}
+
+// the script functions currently running, with the position they were called from
+// shared by all the envs coming from the same root
+#[derive(Debug)]
+struct CallStack {
+    frames: RefCell<Vec<(Name, Position)>>,
+    max_depth: Cell<usize>,
+    // where the native stack was when the running evaluation started, 0 when none is running
+    stack_base: Cell<usize>,
+    // the native stack the running evaluation may use
+    max_stack: Cell<usize>
+}
+
+// a script call takes up to this much native stack, more only for deeply nested bodies
+// evaluations run on a thread with this much stack per allowed call, most of it is never touched
+const STACK_PER_CALL: usize = 64 * 1024;
+// room for the frames between two checks of the stack, and for unwinding
+const STACK_MARGIN: usize = 256 * 1024;
+
+// lets the env and the result of an evaluation move to the thread running it, see CallStack::run
+struct AssertSend<T>(T);
+
+unsafe impl<T> Send for AssertSend<T> {}
+
+// ends an evaluation, even when a native function panics
+struct Running<'a>(&'a CallStack);
+
+impl<'a> Drop for Running<'a> {
+    fn drop(&mut self) {
+        self.0.stack_base.set(0);
+        self.0.frames.borrow_mut().clear();
+    }
+}
+
+// the address of a local, so the distance between two calls is the native stack used in between
+fn stack_pos() -> usize {
+    let marker = 0u8;
+    hint::black_box(&marker) as *const u8 as usize
+}
+
+impl CallStack {
+    // the outermost evaluation runs on its own thread with enough native stack for the maximum call depth,
+    // so it doesn't depend on the stack of the calling thread, nested evaluations stay on that thread
+    // native functions are called there too, so they don't see the thread locals of the caller
+    fn run<F: FnOnce() -> Result<Value, RuntimeError>>(&self, pos: &Position, eval: F) -> Result<Value, RuntimeError> {
+        if self.stack_base.get() != 0 {
+            return eval();
+        }
+        let max_stack = self.max_depth.get().saturating_mul(STACK_PER_CALL);
+        // the calling thread waits for the evaluation to end, so the Rcs they share are never used by both at once
+        let task = AssertSend((self, eval));
+        thread::scope(|scope| {
+            let thread = thread::Builder::new().stack_size(max_stack.saturating_add(STACK_MARGIN)).spawn_scoped(scope, move || {
+                let AssertSend((calls, eval)) = task;
+                let _running = Running(calls);
+                calls.max_stack.set(max_stack);
+                calls.stack_base.set(stack_pos());
+                AssertSend(eval())
+            });
+            match thread.map(|thread| thread.join()) {
+                Ok(Ok(AssertSend(result))) => result,
+                // a native function panicked, the panic goes on in the calling thread
+                Ok(Err(panic)) => panic::resume_unwind(panic),
+                Err(_) => Err(RuntimeErrorType::Unsupported(format!("a call depth of {}", self.max_depth.get())).with_pos(pos.clone()))
+            }
+        })
+    }
+
+    // nested trees and calls not in tail position use native stack, fail cleanly before it runs out
+    fn check_stack(&self, pos: &Position) -> Result<(), RuntimeError> {
+        if self.stack_base.get().abs_diff(stack_pos()) > self.max_stack.get() {
+            return Err(RuntimeErrorType::StackExhausted(self.frames.borrow().clone()).with_pos(pos.clone()));
+        }
+        Ok(())
+    }
+
+    fn push(&self, frame: (Name, Position)) -> Result<(), RuntimeError> {
+        let mut frames = self.frames.borrow_mut();
+        if frames.len() >= self.max_depth.get() {
+            let pos = frame.1.clone();
+            let mut trace = frames.clone();
+            trace.push(frame);
+            return Err(RuntimeErrorType::CallDepthExceeded(trace).with_pos(pos));
+        }
+        frames.push(frame);
+        Ok(())
+    }
+
+    // a tail call takes the place of the call it ends
+    fn replace_top(&self, frame: (Name, Position)) {
+        if let Some(top) = self.frames.borrow_mut().last_mut() {
+            *top = frame;
+        }
+    }
+
+    fn pop(&self) {
+        self.frames.borrow_mut().pop();
+    }
+}
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+// scopes are shared: closures keep a reference to the scope they were created in
+#[derive(Debug, Clone)]
 pub struct Env {
-    values: HashMap<String, Value>
+    scope: Rc<Scope>,
+    calls: Rc<CallStack>
 }
 
 impl Env {
     pub fn new() -> Env {
         Env {
-            values: HashMap::new()
+            scope: Rc::new(Scope {
+                values: RefCell::new(HashMap::new()),
+                parent: None,
+                self_refs: Cell::new(0)
+            }),
+            calls: Rc::new(CallStack {
+                frames: RefCell::new(Vec::new()),
+                max_depth: Cell::new(DEFAULT_MAX_CALL_DEPTH),
+                stack_base: Cell::new(0),
+                max_stack: Cell::new(0)
+            })
+        }
+    }
+
+    // creates a new env whose lookups fall back to this one
+    fn child(&self) -> Env {
+        Env {
+            scope: Rc::new(Scope {
+                values: RefCell::new(HashMap::new()),
+                parent: Some(self.scope.clone()),
+                self_refs: Cell::new(0)
+            }),
+            calls: self.calls.clone()
         }
     }
 
-    fn def(&mut self, name: &String, val: Value) -> Value {
-        if self.values.contains_key(name) {
-            panic!("\"{}\" has already been declared", name);
+    // the number of nested script calls allowed before evaluation fails, calls in tail position don't count
+    // evaluations reserve native stack in proportion
+    pub fn max_call_depth(&self) -> usize {
+        self.calls.max_depth.get()
+    }
+
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.calls.max_depth.set(depth);
+    }
+
+    pub fn def_native<F>(&mut self, name: &str, arity: usize, func: F) -> Result<Value, RuntimeErrorType> where F: Fn(&[Value]) -> Result<Value, RuntimeErrorType> + 'static {
+        self.def(&String::from(name), Value::Native(Rc::new(NativeFunc::new(name, arity, func))))
+    }
+
+    // declares in the innermost frame only, possibly shadowing an outer declaration
+    pub fn def(&mut self, name: &String, val: Value) -> Result<Value, RuntimeErrorType> {
+        let mut values = self.scope.values.borrow_mut();
+        if values.contains_key(name) {
+            return Err(RuntimeErrorType::AlreadyDeclared(name.clone()));
         }
-        self.values.insert(name.clone(), val.clone());
-        val
+        if self.scope.captured_by(&val) {
+            self.scope.self_refs.set(self.scope.self_refs.get() + 1);
+        }
+        values.insert(name.clone(), val.clone());
+        Ok(val)
+    }
+
+    // assigns to the nearest frame that declares the name
+    pub fn set(&mut self, name: &String, val: Value) -> Result<Value, RuntimeErrorType> {
+        let mut scope = Some(&self.scope);
+        while let Some(s) = scope {
+            let old = match s.values.borrow_mut().get_mut(name) {
+                Some(v) => mem::replace(v, val.clone()),
+                None => {
+                    scope = s.parent.as_ref();
+                    continue;
+                }
+            };
+            let self_refs = s.self_refs.get() + s.captured_by(&val) as usize - s.captured_by(&old) as usize;
+            s.self_refs.set(self_refs);
+            // dropped once the frame isn't borrowed anymore, it may release this frame
+            drop(old);
+            return Ok(val);
+        }
+        Err(RuntimeErrorType::Undeclared(name.clone()))
     }
 
-    fn set(&mut self, name: &String, val: Value) -> Value {
-        match self.values.get_mut(name) {
-            Some(v) => *v = val.clone(),
-            None => panic!("\"{}\" was not declared", name)
+    pub fn get(&self, name: &String) -> Result<Value, RuntimeErrorType> {
+        let mut scope = Some(&self.scope);
+        while let Some(s) = scope {
+            if let Some(v) = s.values.borrow().get(name) {
+                return Ok(v.clone());
+            }
+            scope = s.parent.as_ref();
         }
-        val
+        Err(RuntimeErrorType::Undeclared(name.clone()))
+    }
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env::new()
     }
+}
 
-    fn get(&mut self, name: &String) -> Value {
-        match self.values.get(name) {
-            Some(v) => v.clone(),
-            None => panic!("\"{}\" was not declared", name)
+// a function declared in a frame captures that frame, which then holds the function: a reference cycle
+// once only such functions keep the frame alive it's emptied so the cycle is freed
+// cycles going through other frames or through collections are still leaked
+fn release(scope: &Rc<Scope>) {
+    // the reference being released doesn't count
+    let refs = Rc::strong_count(scope) - 1;
+    if refs == 0 || refs != scope.self_refs.get() {
+        return;
+    }
+    // the frame is being modified, the envs it drops are looked at later
+    let values = match scope.values.try_borrow_mut() {
+        Ok(mut values) => mem::take(&mut *values),
+        Err(_) => return
+    };
+    scope.self_refs.set(0);
+    // the values are dropped once the frame isn't borrowed anymore, they may drop envs of this frame
+    drop(values);
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        if let Some(ref parent) = self.parent {
+            release(parent);
         }
     }
 }
 
+impl Drop for Env {
+    fn drop(&mut self) {
+        release(&self.scope);
+    }
+}
+
+impl PartialEq for Env {
+    fn eq(&self, rhs: &Env) -> bool {
+        Rc::ptr_eq(&self.scope, &rhs.scope)
+    }
+}
+
+
+// evaluates the content of a top level block directly in env, so its declarations outlive the evaluation
+pub fn eval_root(tree: &Tree<Name>, env: &mut Env) -> Result<Value, RuntimeError> {
+    match tree.tree_type {
+        TreeType::Block(ref stats, ref expr) => {
+            let calls = env.calls.clone();
+            calls.run(tree.position(), || end_unwind(eval_block(stats, expr, env).and_then(run_tail), tree))
+        },
+        _ => eval(tree, env)
+    }
+}
+
+// what is left to evaluate once the tail position of a tree is reached: nothing, or a call whose result is the result of the tree
+// returning the call instead of making it lets the caller run it without growing the stack
+enum Tail {
+    Done(Value),
+    Call(TailCall)
+}
+
+struct TailCall {
+    body: Rc<Tree<Name>>,
+    env: Env,
+    // the callee and the call position, for stack traces
+    frame: (Name, Position)
+}
+
+// non local exits travel up through eval_tree as errors, until they reach the loop or call that handles them
+enum Unwind {
+    Error(RuntimeError),
+    // the label of the loop to leave, the innermost one if missing
+    Break(Option<Name>, Value),
+    Continue(Option<Name>),
+    Return(Value)
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(e: RuntimeError) -> Unwind {
+        Unwind::Error(e)
+    }
+}
+
+pub fn eval(tree: &Tree<Name>, env: &mut Env) -> Result<Value, RuntimeError> {
+    let calls = env.calls.clone();
+    calls.run(tree.position(), || end_unwind(eval_tree(tree, env), tree))
+}
+
+fn end_unwind(result: Result<Value, Unwind>, tree: &Tree<Name>) -> Result<Value, RuntimeError> {
+    match result {
+        Ok(val) => Ok(val),
+        Err(Unwind::Error(e)) => Err(e),
+        // the parser rejects jumps that have nowhere to go, so this only happens for hand built trees
+        Err(_) => Err(RuntimeErrorType::Unsupported(String::from("jump outside of a loop or function")).with_pos(tree.position().clone()))
+    }
+}
+
+fn eval_tree(tree: &Tree<Name>, env: &mut Env) -> Result<Value, Unwind> {
+    // errors coming from values and envs don't know where they happened, so we attach the tree position here
+    let at = |e: RuntimeErrorType| Unwind::Error(e.with_pos(tree.position().clone()));
+
+    // the bigger cases are evaluated in separate functions, this one being on the stack for every nested tree
+    env.calls.check_stack(tree.position())?;
+    match tree.tree_type {
+        // empty blocks and missing else branches
+        TreeType::Empty => Ok(Value::Unit),
+        TreeType::Ident(ref name) => env.get(name).map_err(at),
+        TreeType::BoolLit(val) => Ok(Value::Bool(val)),
+        TreeType::IntLit(val) => Ok(Value::Int(val)),
+        TreeType::FloatLit(val) => Ok(Value::Float(val)),
+        TreeType::StrLit(ref val) => Ok(Value::Str(val.clone())),
+        TreeType::Func(ref bind, ref body) => Ok(Value::Func(bind.clone(), body.clone(), env.clone())),
+
+        // these can end with a call, which is made here unless they are themselves in tail position
+        TreeType::Call(..) | TreeType::Block(..) | TreeType::If(..) | TreeType::Match(..) => run_tail(eval_tail(tree, env)?),
+
+        TreeType::Break(ref label, ref val) => Err(Unwind::Break(label.clone(), eval_tree(val, env)?)),
+        TreeType::Continue(ref label) => Err(Unwind::Continue(label.clone())),
+        TreeType::Return(ref val) => Err(Unwind::Return(eval_tree(val, env)?)),
+       
+        TreeType::Def(..) | TreeType::DefPattern(..) | TreeType::Assign(..) | TreeType::OpAssign(..) | TreeType::IndexAssign(..) => eval_assign(tree, env),
+
+        TreeType::Add(..) | TreeType::Sub(..) | TreeType::Mul(..) | TreeType::Div(..) | TreeType::Mod(..) |
+        TreeType::Neg(..) | TreeType::Not(..) | TreeType::Pos(..) |
+        TreeType::Eq(..) | TreeType::Neq(..) | TreeType::Lt(..) | TreeType::Le(..) | TreeType::Gt(..) | TreeType::Ge(..) |
+        TreeType::And(..) | TreeType::Or(..) | TreeType::Range(..) => eval_op(tree, env),
+
+        TreeType::Format(..) | TreeType::Tuple(..) | TreeType::List(..) | TreeType::Map(..) | TreeType::Index(..) => eval_collection(tree, env),
+
+        TreeType::While(..) | TreeType::For(..) => eval_loop(tree, env),
+
+        _ => Err(at(RuntimeErrorType::Unsupported(format!("{}", tree))))
+    }
+}
+
+// definitions and assignments
+fn eval_assign(tree: &Tree<Name>, env: &mut Env) -> Result<Value, Unwind> {
+    let at = |e: RuntimeErrorType| Unwind::Error(e.with_pos(tree.position().clone()));
 
-pub fn eval(tree: &Tree<Name>, env: &mut Env) -> Value {
     match tree.tree_type {
-        TreeType::Def(ref name, ref rhs) => {
-            let val = eval(rhs, env);
-            env.def(name, val)
+        TreeType::Def(ref name, ref rhs, ..) => {
+            let val = eval_tree(rhs, env)?;
+            env.def(name, val).map_err(at)
+        },
+
+        TreeType::DefPattern(ref pattern, ref rhs) => {
+            let val = eval_tree(rhs, env)?;
+            bind_pattern(pattern, val.clone(), env).map_err(at)?;
+            Ok(val)
         },
 
         TreeType::Assign(ref name, ref rhs) => {
-            let val = eval(rhs, env);
-            env.set(name, val)
+            let val = eval_tree(rhs, env)?;
+            env.set(name, val).map_err(at)
+        },
+
+        TreeType::OpAssign(..) | TreeType::IndexAssign(..) => eval_update(tree, env),
+
+        _ => unreachable!()
+    }
+}
+
+// assignments to an element, or through an operator
+// kept out of eval_assign, so plain declarations nested in recursive calls use less stack
+#[inline(never)]
+fn eval_update(tree: &Tree<Name>, env: &mut Env) -> Result<Value, Unwind> {
+    let at = |e: RuntimeErrorType| Unwind::Error(e.with_pos(tree.position().clone()));
+
+    match tree.tree_type {
+        // the target is only evaluated once, so 'a[f()] += 1' calls f a single time
+        TreeType::OpAssign(op, ref lhs, ref rhs) => {
+            let (name, path) = eval_place(lhs, env)?;
+            let root = env.get(&name).map_err(|e| e.with_pos(lhs.position().clone()))?;
+            let current = load(&root, &path)?;
+            let rhs = eval_tree(rhs, env)?;
+            let val = match op {
+                AssignOp::Add => current + rhs,
+                AssignOp::Sub => current - rhs,
+                AssignOp::Mul => current * rhs,
+                AssignOp::Div => current / rhs
+            }.map_err(at)?;
+            let root = store(root, &path, val.clone())?;
+            env.set(&name, root).map_err(at)?;
+            Ok(val)
         },
 
-        TreeType::Ident(ref name) => env.get(name),
-        TreeType::IntLit(val) => Value::Num(val as f64),
-        TreeType::StrLit(ref val) => Value::Str(val.clone()),
+        TreeType::IndexAssign(ref lhs, ref index, ref rhs) => {
+            let (name, mut path) = eval_place(lhs, env)?;
+            path.push((eval_tree(index, env)?, tree.position().clone()));
+            let val = eval_tree(rhs, env)?;
+            let root = env.get(&name).map_err(|e| e.with_pos(lhs.position().clone()))?;
+            let root = store(root, &path, val.clone())?;
+            env.set(&name, root).map_err(at)?;
+            Ok(val)
+        },
+
+        _ => unreachable!()
+    }
+}
+
+// operators, both operands of a binary one are evaluated before applying it (except for '&&' and '||')
+fn eval_op(tree: &Tree<Name>, env: &mut Env) -> Result<Value, Unwind> {
+    let at = |e: RuntimeErrorType| Unwind::Error(e.with_pos(tree.position().clone()));
+
+    let (lhs, rhs) = match tree.tree_type {
+        TreeType::Neg(ref expr) => return (-eval_tree(expr, env)?).map_err(at),
+        TreeType::Not(ref expr) => return (!eval_tree(expr, env)?).map_err(at),
+        // unary plus leaves numbers untouched, but still rejects anything else
+        TreeType::Pos(ref expr) => return match eval_tree(expr, env)? {
+            val @ Value::Int(_) | val @ Value::Float(_) => Ok(val),
+            x => Err(at(RuntimeErrorType::TypeMismatch(format!("{:?} is not a number", x))))
+        },
+
+        // the rhs is only evaluated if the lhs doesn't decide the result
+        TreeType::And(ref lhs, ref rhs) => return Ok(Value::Bool(eval_bool(lhs, env)? && eval_bool(rhs, env)?)),
+        TreeType::Or(ref lhs, ref rhs) => return Ok(Value::Bool(eval_bool(lhs, env)? || eval_bool(rhs, env)?)),
+
+        TreeType::Add(ref lhs, ref rhs) | TreeType::Sub(ref lhs, ref rhs) | TreeType::Mul(ref lhs, ref rhs) |
+        TreeType::Div(ref lhs, ref rhs) | TreeType::Mod(ref lhs, ref rhs) |
+        TreeType::Eq(ref lhs, ref rhs) | TreeType::Neq(ref lhs, ref rhs) |
+        TreeType::Lt(ref lhs, ref rhs) | TreeType::Le(ref lhs, ref rhs) | TreeType::Gt(ref lhs, ref rhs) | TreeType::Ge(ref lhs, ref rhs) |
+        TreeType::Range(ref lhs, ref rhs, _) => (lhs, rhs),
+        _ => unreachable!()
+    };
+
+    let lhs = eval_tree(lhs, env)?;
+    let rhs = eval_tree(rhs, env)?;
+    match tree.tree_type {
+        TreeType::Add(..) => lhs + rhs,
+        TreeType::Sub(..) => lhs - rhs,
+        TreeType::Mul(..) => lhs * rhs,
+        TreeType::Div(..) => lhs / rhs,
+        TreeType::Mod(..) => lhs % rhs,
+
+        TreeType::Eq(..) => Ok(Value::Bool(lhs == rhs)),
+        TreeType::Neq(..) => Ok(Value::Bool(lhs != rhs)),
+        TreeType::Lt(..) => lhs.compare(&rhs).map(|o| Value::Bool(o == Some(Ordering::Less))),
+        TreeType::Le(..) => lhs.compare(&rhs).map(|o| Value::Bool(matches!(o, Some(Ordering::Less) | Some(Ordering::Equal)))),
+        TreeType::Gt(..) => lhs.compare(&rhs).map(|o| Value::Bool(o == Some(Ordering::Greater))),
+        TreeType::Ge(..) => lhs.compare(&rhs).map(|o| Value::Bool(matches!(o, Some(Ordering::Greater) | Some(Ordering::Equal)))),
 
-        TreeType::Func(ref bind, ref body) => Value::Func(bind.iter().map(|b| b.ident_name().unwrap().clone()).collect(), body.clone()),
+        TreeType::Range(_, _, inclusive) => range(lhs, rhs, inclusive),
+        _ => unreachable!()
+    }.map_err(at)
+}
 
-        TreeType::Add(ref lhs, ref rhs) => eval(lhs, env) + eval(rhs, env),
-        TreeType::Sub(ref lhs, ref rhs) => eval(lhs, env) - eval(rhs, env),
-        TreeType::Mul(ref lhs, ref rhs) => eval(lhs, env) * eval(rhs, env),
-        TreeType::Div(ref lhs, ref rhs) => eval(lhs, env) / eval(rhs, env),
+fn range(start: Value, end: Value, inclusive: bool) -> Result<Value, RuntimeErrorType> {
+    let start = start.to_int()?;
+    let end = end.to_int()?;
+    Ok(Value::Range(start, end, inclusive))
+}
 
-        TreeType::Eq(ref lhs, ref rhs) => Value::Num(if eval(lhs, env) == eval(rhs, env) { 1.0 } else { 0.0 }),
-        TreeType::Neq(ref lhs, ref rhs) => Value::Num(if eval(lhs, env) != eval(rhs, env) { 1.0 } else { 0.0 }),
+// strings, collections and indexing
+fn eval_collection(tree: &Tree<Name>, env: &mut Env) -> Result<Value, Unwind> {
+    let at = |e: RuntimeErrorType| Unwind::Error(e.with_pos(tree.position().clone()));
 
-        TreeType::Call(ref func, ref args) => {
-            let (params, body) = eval(func, env).to_func();
-            if args.len() != params.len() {
-                panic!("invalid number of arguments: expected {}, got {}", params.len(), args.len());
+    match tree.tree_type {
+        TreeType::Format(ref parts) => {
+            let mut string = String::new();
+            for (p, spec) in parts {
+                let val = eval_tree(p, env)?;
+                match *spec {
+                    Some(ref spec) => string.push_str(&spec.apply(&val).map_err(|e| e.with_pos(p.position().clone()))?),
+                    None => string.push_str(&val.to_str())
+                }
             }
-            let mut inner = Env::new();
-            // declare all args in the called env
-            for (a, p) in args.into_iter().zip(params) {
-                inner.def(&p, eval(a, env));
+            Ok(Value::Str(string))
+        },
+
+        TreeType::Tuple(ref elems) => Ok(Value::Tuple(elems.iter().map(|e| eval_tree(e, env)).collect::<Result<_, _>>()?)),
+        TreeType::List(ref elems) => Ok(Value::List(elems.iter().map(|e| eval_tree(e, env)).collect::<Result<_, _>>()?)),
+        TreeType::Map(ref entries) => {
+            let mut map = Map::new();
+            for (k, v) in entries {
+                let key = Key::from_value(&eval_tree(k, env)?).map_err(|e| e.with_pos(k.position().clone()))?;
+                map.insert(key, eval_tree(v, env)?);
             }
-            // call
-            eval(body.as_ref(), &mut inner)
+            Ok(Value::Map(map))
         },
 
-        TreeType::Block(ref stats, ref expr) => {
-            for s in stats {
-                eval(s, env);
+        TreeType::Index(ref lhs, ref index) => {
+            let container = eval_tree(lhs, env)?;
+            let index = eval_tree(index, env)?;
+            container.index(&index).map_err(at)
+        },
+
+        _ => unreachable!()
+    }
+}
+
+fn eval_loop(tree: &Tree<Name>, env: &mut Env) -> Result<Value, Unwind> {
+    let at = |e: RuntimeErrorType| Unwind::Error(e.with_pos(tree.position().clone()));
+
+    match tree.tree_type {
+        TreeType::While(ref cond, ref body, ref label) => {
+            while eval_bool(cond, env)? {
+                if let Some(val) = eval_loop_body(body, label, env)? {
+                    return Ok(val);
+                }
             }
-            eval(expr, env)
+            Ok(Value::Unit)
         },
 
-        TreeType::Tuple(ref elems) => Value::Tuple(elems.iter().map(|e| eval(e, env)).collect()),
+        TreeType::For(ref bind, ref iter, ref body, ref label) => {
+            for val in eval_tree(iter, env)?.to_iter().map_err(at)? {
+                // each iteration gets a fresh frame for the loop variables
+                let mut inner = env.child();
+                bind_pattern(bind, val, &mut inner).map_err(at)?;
+                if let Some(val) = eval_loop_body(body, label, &mut inner)? {
+                    return Ok(val);
+                }
+            }
+            Ok(Value::Unit)
+        },
+
+        _ => unreachable!()
+    }
+}
+
+// runs the statements of a block in env, leaving its last expression in tail position
+// 'fn' declarations are defined first, so they can call each other whatever their order
+fn eval_block(stats: &[Tree<Name>], expr: &Tree<Name>, env: &mut Env) -> Result<Tail, Unwind> {
+    let is_hoisted = |s: &&Tree<Name>| matches!(s.tree_type, TreeType::Def(_, _, _, true));
+    for s in stats.iter().chain(iter::once(expr)).filter(is_hoisted) {
+        eval_tree(s, env)?;
+    }
+    for s in stats.iter().filter(|s| !is_hoisted(s)) {
+        eval_tree(s, env)?;
+    }
+    match expr.tree_type {
+        TreeType::Def(ref name, _, _, true) => Ok(Tail::Done(env.get(name).map_err(|e| e.with_pos(expr.position().clone()))?)),
+        _ => eval_tail(expr, env)
+    }
+}
+
+// evaluates tree, except for a script call in tail position (the end of a block, an if branch or a match arm) which is returned
+fn eval_tail(tree: &Tree<Name>, env: &mut Env) -> Result<Tail, Unwind> {
+    // nested blocks and ifs recurse here without going through eval_tree
+    env.calls.check_stack(tree.position())?;
+    match tree.tree_type {
+        TreeType::Call(ref func, ref args, ref named) => eval_call(tree, func, args, named, env),
+
+        TreeType::Block(ref stats, ref expr) => {
+            // each block gets its own frame, which is dropped once the block is done
+            let mut inner = env.child();
+            eval_block(stats, expr, &mut inner)
+        },
 
         TreeType::If(ref cond, ref thenp, ref elsep) => 
-            if eval(cond, env).to_bool() {
-                eval(thenp, env)
+            if eval_bool(cond, env)? {
+                eval_tail(thenp, env)
             } else {
-                eval(elsep, env)
+                eval_tail(elsep, env)
+            },
+
+        TreeType::Match(ref expr, ref arms) => eval_match(tree, expr, arms, env),
+
+        _ => Ok(Tail::Done(eval_tree(tree, env)?))
+    }
+}
+
+// kept out of eval_tail, which is on the stack for every nested block and if
+#[inline(never)]
+fn eval_match(tree: &Tree<Name>, expr: &Tree<Name>, arms: &[MatchArm<Name>], env: &mut Env) -> Result<Tail, Unwind> {
+    let val = eval_tree(expr, env)?;
+    for (pattern, guard, body) in arms {
+        // bindings of an arm are only visible in its guard and body
+        let mut inner = env.child();
+        if !match_pattern(pattern, &val, &mut inner).map_err(|e| e.with_pos(tree.position().clone()))? {
+            continue;
+        }
+        if let Some(guard) = guard {
+            if !eval_bool(guard, &mut inner)? {
+                continue;
+            }
+        }
+        return eval_tail(body, &mut inner);
+    }
+    Err(RuntimeErrorType::NoMatch(format!("{:?}", val)).with_pos(tree.position().clone()).into())
+}
+
+// evaluates the callee and the arguments, natives are called right away
+#[inline(never)]
+fn eval_call(tree: &Tree<Name>, func: &Tree<Name>, args: &[Tree<Name>], named: &[NamedArg<Name>], env: &mut Env) -> Result<Tail, Unwind> {
+    let at = |e: RuntimeErrorType| Unwind::Error(e.with_pos(tree.position().clone()));
+    let callee = match func.tree_type {
+        TreeType::Ident(ref name) => name.clone(),
+        _ => format!("{}", func)
+    };
+
+    let func = eval_tree(func, env)?;
+    if let Value::Native(ref native) = func {
+        // natives only take positional arguments
+        if let Some((name, a)) = named.first() {
+            return Err(RuntimeErrorType::UnknownArgument(name.clone()).with_pos(a.position().clone()).into());
+        }
+        if args.len() != native.arity {
+            return Err(at(RuntimeErrorType::ArityMismatch(native.arity, args.len())));
+        }
+        let args = args.iter().map(|a| eval_tree(a, env)).collect::<Result<Vec<_>, _>>()?;
+        return Ok(Tail::Done(native.call(&args).map_err(at)?));
+    }
+    let (params, body, captured) = func.to_func().map_err(at)?;
+    let (params, rest) = match params.split_last() {
+        Some(((Pattern::Rest(name), _), params)) => (params, Some(name)),
+        _ => (&params[..], None)
+    };
+    if args.len() > params.len() && rest.is_none() {
+        return Err(at(RuntimeErrorType::ArityMismatch(params.len(), args.len())));
+    }
+
+    // positional arguments fill the parameters in order, the extra ones go to the rest
+    let mut vals: Vec<Option<(Value, &Tree<Name>)>> = vec![None; params.len()];
+    let mut extra = Vec::new();
+    for (i, a) in args.iter().enumerate() {
+        let val = eval_tree(a, env)?;
+        if i < params.len() {
+            vals[i] = Some((val, a));
+        } else {
+            extra.push(val);
+        }
+    }
+    for (name, a) in named {
+        let arg_at = |e: RuntimeErrorType| Unwind::Error(e.with_pos(a.position().clone()));
+        let i = params.iter().position(|p| matches!(p.0, Pattern::Bind(ref n) if n == name))
+            .ok_or_else(|| arg_at(RuntimeErrorType::UnknownArgument(name.clone())))?;
+        if vals[i].is_some() {
+            return Err(arg_at(RuntimeErrorType::DuplicateArgument(name.clone())));
+        }
+        vals[i] = Some((eval_tree(a, env)?, a));
+    }
+
+    // the body runs in a new scope on top of the one the function was created in
+    let mut inner = captured.child();
+    // declare all args in the called env
+    for ((pattern, default), val) in params.iter().zip(vals) {
+        let (val, a) = match (val, default) {
+            (Some(val), _) => val,
+            // defaults are evaluated at each call, after the parameters before them are declared
+            (None, Some(default)) => (eval_tree(default, &mut inner)?, default),
+            (None, None) => {
+                let required = params.iter().filter(|p| p.1.is_none()).count();
+                return Err(at(RuntimeErrorType::ArityMismatch(required, args.len() + named.len())));
+            }
+        };
+        bind_pattern(pattern, val, &mut inner).map_err(|e| e.with_pos(a.position().clone()))?;
+    }
+    if let Some(Some(name)) = rest {
+        inner.def(name, Value::List(extra)).map_err(at)?;
+    }
+    Ok(Tail::Call(TailCall { body, env: inner, frame: (callee, tree.position().clone()) }))
+}
+
+// makes a call and then the calls in tail position it leads to, all in the same stack frame
+fn run_tail(tail: Tail) -> Result<Value, Unwind> {
+    let mut call = match tail {
+        Tail::Done(val) => return Ok(val),
+        Tail::Call(call) => call
+    };
+    let calls = call.env.calls.clone();
+    calls.push(call.frame.clone())?;
+    let result = loop {
+        match eval_tail(&call.body, &mut call.env) {
+            Ok(Tail::Call(next)) => {
+                calls.replace_top(next.frame.clone());
+                call = next;
             },
+            // a return ends the whole chain of tail calls
+            Ok(Tail::Done(val)) | Err(Unwind::Return(val)) => break Ok(val),
+            Err(e) => break Err(e)
+        }
+    };
+    calls.pop();
+    result
+}
 
-        TreeType::While(ref cond, ref body) => {
-            while eval(cond, env).to_bool() {
-                eval(body, env);
+// checks whether val has the shape of pattern, declaring the names it binds in env
+fn match_pattern(pattern: &Pattern<Name>, val: &Value, env: &mut Env) -> Result<bool, RuntimeErrorType> {
+    match (pattern, val) {
+        (Pattern::Wildcard, _) => Ok(true),
+        (Pattern::Bind(name), _) => env.def(name, val.clone()).map(|_| true),
+        (Pattern::Lit(lit), _) => Ok(*val == lit_value(lit)?),
+        (Pattern::Tuple(pats), Value::Tuple(vals)) => match_elems(pats, vals, env),
+        (Pattern::List(pats), Value::List(vals)) => {
+            match pats.iter().position(|p| matches!(p, Pattern::Rest(_))) {
+                None => match_elems(pats, vals, env),
+                Some(rest) => {
+                    // the elements around the rest are matched from both ends
+                    let after = pats.len() - rest - 1;
+                    if vals.len() < rest + after {
+                        return Ok(false);
+                    }
+                    let middle = vals.len() - after;
+                    if !match_elems(&pats[..rest], &vals[..rest], env)? || !match_elems(&pats[rest + 1..], &vals[middle..], env)? {
+                        return Ok(false);
+                    }
+                    if let Pattern::Rest(Some(ref name)) = pats[rest] {
+                        env.def(name, Value::List(vals[rest..middle].to_vec()))?;
+                    }
+                    Ok(true)
+                }
             }
-            Value::Unit
         },
-       
-        _ => panic!("\"{}\" not supported", tree)
+        _ => Ok(false)
+    }
+}
+
+fn match_elems(pats: &[Pattern<Name>], vals: &[Value], env: &mut Env) -> Result<bool, RuntimeErrorType> {
+    if pats.len() != vals.len() {
+        return Ok(false);
+    }
+    for (p, v) in pats.iter().zip(vals) {
+        if !match_pattern(p, v, env)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// the parser only puts literals in patterns
+fn lit_value(lit: &Tree<Name>) -> Result<Value, RuntimeErrorType> {
+    match lit.tree_type {
+        TreeType::BoolLit(val) => Ok(Value::Bool(val)),
+        TreeType::IntLit(val) => Ok(Value::Int(val)),
+        TreeType::FloatLit(val) => Ok(Value::Float(val)),
+        TreeType::StrLit(ref val) => Ok(Value::Str(val.clone())),
+        _ => Err(RuntimeErrorType::Unsupported(format!("{}", lit)))
+    }
+}
+
+// runs one iteration of a loop, returning the value of the break that ended the loop if any
+// jumps to an outer loop keep unwinding
+fn eval_loop_body(body: &Tree<Name>, label: &Option<Name>, env: &mut Env) -> Result<Option<Value>, Unwind> {
+    let targets = |target: &Option<Name>| target.is_none() || target == label;
+    match eval_tree(body, env) {
+        Ok(_) => Ok(None),
+        Err(Unwind::Continue(ref target)) if targets(target) => Ok(None),
+        Err(Unwind::Break(ref target, ref val)) if targets(target) => Ok(Some(val.clone())),
+        Err(e) => Err(e)
+    }
+}
+
+// declares the names a pattern binds (in let, for and parameters), destructuring val
+// unlike in match, a value that doesn't fit is an error
+fn bind_pattern(pattern: &Pattern<Name>, val: Value, env: &mut Env) -> Result<(), RuntimeErrorType> {
+    match *pattern {
+        Pattern::Wildcard => Ok(()),
+        Pattern::Bind(ref name) => env.def(name, val).map(|_| ()),
+        Pattern::Lit(ref lit) => if val == lit_value(lit)? { Ok(()) } else { Err(RuntimeErrorType::NoMatch(format!("{:?}", val))) },
+        Pattern::Tuple(ref pats) | Pattern::List(ref pats) => {
+            let mut vals = match val {
+                Value::Tuple(vals) | Value::List(vals) => vals,
+                x => return Err(RuntimeErrorType::TypeMismatch(format!("{:?} can not be destructured", x)))
+            };
+            match pats.iter().position(|p| matches!(p, Pattern::Rest(_))) {
+                None => {
+                    if vals.len() != pats.len() {
+                        return Err(RuntimeErrorType::ArityMismatch(pats.len(), vals.len()));
+                    }
+                    for (p, v) in pats.iter().zip(vals) {
+                        bind_pattern(p, v, env)?;
+                    }
+                    Ok(())
+                },
+                Some(rest) => {
+                    // at least the elements around the rest are needed
+                    if vals.len() < pats.len() - 1 {
+                        return Err(RuntimeErrorType::ArityMismatch(pats.len() - 1, vals.len()));
+                    }
+                    let after = vals.split_off(vals.len() - (pats.len() - rest - 1));
+                    let middle = vals.split_off(rest);
+                    for (p, v) in pats[..rest].iter().zip(vals).chain(pats[rest + 1..].iter().zip(after)) {
+                        bind_pattern(p, v, env)?;
+                    }
+                    if let Pattern::Rest(Some(ref name)) = pats[rest] {
+                        env.def(name, Value::List(middle))?;
+                    }
+                    Ok(())
+                }
+            }
+        },
+        Pattern::Rest(_) => Err(RuntimeErrorType::Unsupported(format!("{}", pattern)))
+    }
+}
+
+fn eval_bool(tree: &Tree<Name>, env: &mut Env) -> Result<bool, Unwind> {
+    Ok(eval_tree(tree, env)?.to_bool().map_err(|e| e.with_pos(tree.position().clone()))?)
+}
+
+// evaluates an assignable tree into the variable it designates and the indices leading to the target element
+fn eval_place(tree: &Tree<Name>, env: &mut Env) -> Result<(Name, Path), Unwind> {
+    match tree.tree_type {
+        TreeType::Ident(ref name) => Ok((name.clone(), Vec::new())),
+        TreeType::Index(ref lhs, ref index) => {
+            let (name, mut path) = eval_place(lhs, env)?;
+            path.push((eval_tree(index, env)?, tree.position().clone()));
+            Ok((name, path))
+        },
+        _ => Err(RuntimeErrorType::Unsupported(format!("{}", tree)).with_pos(tree.position().clone()).into())
     }
-}
\ No newline at end of file
+}
+
+// reads the element at the end of path in container
+fn load(container: &Value, path: &[(Value, Position)]) -> Result<Value, RuntimeError> {
+    let mut val = container.clone();
+    for (index, pos) in path {
+        val = val.index(index).map_err(|e| e.with_pos(pos.clone()))?;
+    }
+    Ok(val)
+}
+
+// replaces the element at the end of path in container, returning the updated container
+fn store(container: Value, path: &[(Value, Position)], val: Value) -> Result<Value, RuntimeError> {
+    if let Some(((index, pos), rest)) = path.split_first() {
+        let elem = if rest.is_empty() {
+            val
+        } else {
+            let inner = container.index(index).map_err(|e| e.with_pos(pos.clone()))?;
+            store(inner, rest, val)?
+        };
+        container.set_index(index, elem).map_err(|e| e.with_pos(pos.clone()))
+    } else {
+        Ok(val)
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+
+use value::*;
+use error::*;
+
+// bigger widths and precisions are rejected, so a format can't allocate without bounds
+const MAX_WIDTH: usize = 1024;
+
+// the part after ':' in an interpolation, like "{x:>8.2}": [<|>][0][width][.precision]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FmtSpec {
+    pub align: Option<char>,
+    pub zero: bool,
+    pub width: Option<usize>,
+    pub precision: Option<usize>
+}
+
+impl FmtSpec {
+    pub fn parse(spec: &str) -> Option<FmtSpec> {
+        let mut chars = spec.chars().peekable();
+        let align = match chars.peek() {
+            Some(&c) if c == '<' || c == '>' => { chars.next(); Some(c) },
+            _ => None
+        };
+        let zero = chars.peek() == Some(&'0');
+        if zero {
+            chars.next();
+        }
+        let rest: String = chars.collect();
+        let (width, precision) = match rest.find('.') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (&rest[..], None)
+        };
+        let width = if width.is_empty() { None } else { Some(width.parse().ok().filter(|&w| w <= MAX_WIDTH)?) };
+        let precision = match precision {
+            Some(p) => Some(p.parse().ok().filter(|&p| p <= MAX_WIDTH)?),
+            None => None
+        };
+        Some(FmtSpec { align, zero, width, precision })
+    }
+
+    // precision is the number of decimals for floats and truncates strings, other values have none,
+    // numbers are right aligned by default and everything else left aligned
+    pub fn apply(&self, val: &Value) -> Result<String, RuntimeErrorType> {
+        let body = match (val, self.precision) {
+            (&Value::Float(v), Some(p)) => format!("{:.*}", p, v),
+            (Value::Str(s), Some(p)) => s.chars().take(p).collect(),
+            (val, Some(_)) => return Err(RuntimeErrorType::TypeMismatch(format!("{:?} can not be formatted with a precision", val))),
+            (val, None) => format!("{}", val)
+        };
+        let len = body.chars().count();
+        let width = self.width.unwrap_or(0);
+        if len >= width {
+            return Ok(body);
+        }
+
+        let is_num = matches!(*val, Value::Int(_) | Value::Float(_));
+        let padding = width - len;
+        if self.zero && is_num {
+            // zeros go between the sign and the digits
+            let (sign, digits) = if body.starts_with('-') { body.split_at(1) } else { body.split_at(0) };
+            Ok(format!("{}{}{}", sign, "0".repeat(padding), digits))
+        } else if self.align == Some('<') || (self.align.is_none() && !is_num) {
+            Ok(body + &" ".repeat(padding))
+        } else {
+            Ok(" ".repeat(padding) + &body)
+        }
+    }
+}
+
+impl fmt::Display for FmtSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(align) = self.align {
+            write!(f, "{}", align)?;
+        }
+        if self.zero {
+            write!(f, "0")?;
+        }
+        if let Some(width) = self.width {
+            write!(f, "{}", width)?;
+        }
+        if let Some(precision) = self.precision {
+            write!(f, ".{}", precision)?;
+        }
+        Ok(())
+    }
+}
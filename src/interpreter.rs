@@ -0,0 +1,64 @@
+use tree::*;
+use tokenizer::*;
+use parser;
+use prelude;
+use eval::*;
+use value::*;
+use error::*;
+
+type Name = String;
+
+// a persistent session: everything declared at the top level of a snippet is visible to the next ones
+// the built-in functions from the prelude are declared in the global env
+// frames are reference counted: a function stored in a collection or assigned to an outer name
+// that captures its own frame keeps that frame alive, it is never freed
+pub struct Interpreter {
+    env: Env
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        let mut env = Env::new();
+        prelude::install(&mut env).unwrap();
+        Interpreter {
+            env
+        }
+    }
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    pub fn env_mut(&mut self) -> &mut Env {
+        &mut self.env
+    }
+
+    // makes a Rust function callable from scripts under the given name
+    pub fn register<F>(&mut self, name: &str, arity: usize, func: F) -> Result<(), RuntimeErrorType> where F: Fn(&[Value]) -> Result<Value, RuntimeErrorType> + 'static {
+        self.env.def_native(name, arity, func).map(|_| ())
+    }
+
+    pub fn parse_str(&self, input: &str) -> Result<Tree<Name>, Error> {
+        let tree = parser::parse(&mut Tokenizer::tokenize(input.chars()));
+        let mut errors = Vec::new();
+        tree.for_each(|t| if let TreeType::Error(message) = t.tree_type {
+            errors.push(ParseError { message, pos: t.position().clone() });
+        });
+        if errors.is_empty() {
+            Ok(tree)
+        } else {
+            Err(Error::Parse(errors))
+        }
+    }
+
+    pub fn eval_str(&mut self, input: &str) -> Result<Value, Error> {
+        let tree = self.parse_str(input)?;
+        Ok(eval_root(&tree, &mut self.env)?)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
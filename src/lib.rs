@@ -0,0 +1,22 @@
+pub mod position;
+pub mod tokenizer;
+pub mod token;
+pub mod tree;
+pub mod parser;
+pub mod eval;
+pub mod value;
+pub mod format;
+pub mod map;
+pub mod error;
+pub mod interpreter;
+pub mod prelude;
+mod map_in_place;
+mod typing;
+
+#[cfg(test)]
+mod tests;
+
+pub use interpreter::Interpreter;
+pub use value::{Value, NativeFunc};
+pub use eval::Env;
+pub use error::{Error, ParseError, RuntimeError, RuntimeErrorType};
@@ -1,18 +1,10 @@
-use std::time::Instant;
+extern crate script_rs;
 
-mod position;
-mod tokenizer;
-mod token;
-mod tree;
-mod parser;
-mod eval;
-mod value;
-mod map_in_place;
-mod tests;
-mod typing;
+use std::time::Instant;
 
-use tree::*;
-use tokenizer::*;
+use script_rs::tree::*;
+use script_rs::tokenizer::*;
+use script_rs::parser;
 
 fn collect_errors<'a>(tree: &'a Tree<String>) -> Vec<&'a Tree<String>> {
     let mut err: Vec<&'a Tree<String>> = Vec::new();
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::slice;
+
+use value::*;
+use error::*;
+
+// maps are keyed by strings or ints
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Str(String),
+    Int(i64)
+}
+
+// an associative container that iterates in insertion order
+// (re-inserting an existing key keeps its position, removing a key preserves the order of the others)
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    entries: Vec<(Key, Value)>,
+    indices: HashMap<Key, usize>
+}
+
+impl Key {
+    pub fn from_value(val: &Value) -> Result<Key, RuntimeErrorType> {
+        match *val {
+            Value::Str(ref s) => Ok(Key::Str(s.clone())),
+            Value::Int(n) => Ok(Key::Int(n)),
+            ref x => Err(RuntimeErrorType::TypeMismatch(format!("{:?} can not be used as a key", x)))
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match *self {
+            Key::Str(ref s) => Value::Str(s.clone()),
+            Key::Int(n) => Value::Int(n)
+        }
+    }
+}
+
+impl Map {
+    pub fn new() -> Map {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.indices.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: Key, val: Value) {
+        if let Some(&i) = self.indices.get(&key) {
+            self.entries[i].1 = val;
+        } else {
+            self.indices.insert(key.clone(), self.entries.len());
+            self.entries.push((key, val));
+        }
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        let i = self.indices.remove(key)?;
+        let (_, val) = self.entries.remove(i);
+        for (k, _) in &self.entries[i..] {
+            *self.indices.get_mut(k).unwrap() -= 1;
+        }
+        Some(val)
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, (Key, Value)> {
+        self.entries.iter()
+    }
+}
+
+// two maps are equal if they have the same entries, whatever their order
+impl PartialEq for Map {
+    fn eq(&self, rhs: &Map) -> bool {
+        self.len() == rhs.len() && self.iter().all(|(k, v)| rhs.get(k) == Some(v))
+    }
+}
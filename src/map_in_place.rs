@@ -21,13 +21,11 @@ impl<T> MapInPlace<T> for Box<T> {
 }
 
 impl<T: Clone> MapInPlace<T> for Rc<T> {
-    fn map_in_place<F: FnMut(T) -> T>(mut self, mut map: F) -> Rc<T> {
-        unsafe  {
-            let ptr: *mut T = Rc::get_mut(&mut self).unwrap();
-            let content = ptr::read(ptr);
-            let res = map(content);
-            ptr::write(ptr, res);
-            Rc::from_raw(ptr)
+    // a shared content is cloned, the other owners keep the unmapped one
+    fn map_in_place<F: FnMut(T) -> T>(self, mut map: F) -> Rc<T> {
+        match Rc::try_unwrap(self) {
+            Ok(content) => Rc::new(map(content)),
+            Err(shared) => Rc::new(map((*shared).clone()))
         }
     }
 }
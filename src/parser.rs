@@ -1,22 +1,115 @@
 
 use std::iter::Peekable;
 use std::rc::Rc;
+use std::mem;
 
 use tree::*;
 use token::*;
 use position::*;
 
 type Name = String;
+// positional and named arguments of a call
+type Args = (Vec<Tree<Name>>, Vec<NamedArg<Name>>);
 
 const FORCE_BLOCK_BRACES: bool = false;
 const FOLD_TUPLE_1: bool = true;
 
 
+// the token stream, along with what the parser is currently inside of, so misplaced control flow can be rejected
+struct Tokens<I: Iterator<Item = Token>> {
+    tokens: Peekable<I>,
+    // the labels of the enclosing loops, innermost last
+    loops: Vec<Option<Name>>,
+    in_func: bool,
+    // the doc comments between the last token read and the next one, they're skipped everywhere else
+    docs: Vec<String>,
+    // the line of the last token read
+    line: usize
+}
+
+impl<I: Iterator<Item = Token>> Tokens<I> {
+    fn new(tokens: I) -> Tokens<I> {
+        Tokens {
+            tokens: tokens.peekable(),
+            loops: Vec::new(),
+            in_func: false,
+            docs: Vec::new(),
+            line: 0
+        }
+    }
+
+    fn skip_docs(&mut self) {
+        while let Some(Token { token: TokenType::DocComment(line), .. }) = self.tokens.peek() {
+            self.docs.push(line.clone());
+            self.tokens.next();
+        }
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        self.skip_docs();
+        self.tokens.peek()
+    }
+
+    // the doc comment in front of the next token, if any
+    fn take_doc(&mut self) -> Option<String> {
+        self.skip_docs();
+        if self.docs.is_empty() {
+            None
+        } else {
+            Some(mem::take(&mut self.docs).join("\n"))
+        }
+    }
+
+    fn in_loop(&self) -> bool {
+        !self.loops.is_empty()
+    }
+
+    fn has_label(&self, label: &Name) -> bool {
+        self.loops.iter().any(|l| l.as_ref() == Some(label))
+    }
+
+    // loop bodies can be left with 'break' and 'continue'
+    fn loop_body(&mut self, label: Option<Name>) -> Tree<Name> {
+        self.loops.push(label);
+        let body = parse_block(self);
+        self.loops.pop();
+        body
+    }
+
+    // a function body can return, but not leave the loops around the function
+    fn func_body<T, F: FnOnce(&mut Tokens<I>) -> T>(&mut self, parse: F) -> T {
+        let loops = mem::take(&mut self.loops);
+        let in_func = mem::replace(&mut self.in_func, true);
+        let r = parse(self);
+        self.loops = loops;
+        self.in_func = in_func;
+        r
+    }
+}
+
+impl<I: Iterator<Item = Token>> Iterator for Tokens<I> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.skip_docs();
+        let token = self.tokens.next();
+        self.docs.clear();
+        if let Some(ref token) = token {
+            self.line = token.pos.line();
+        }
+        token
+    }
+}
+
+
 pub fn parse<I: Iterator<Item = Token>>(tokens: &mut I) -> Tree<Name> {
-    let peekable = &mut tokens.peekable();
+    let peekable = &mut Tokens::new(tokens);
 
-    // parse block
-    let block = parse_block(peekable);
+    // parse block, a rest that didn't end up in lambda parameters is misplaced
+    let block = parse_block(peekable).transform(|t| match t {
+        TreeType::Rest(_) => TreeType::Error("'..' outside of a pattern"),
+        t => t
+    });
 
     // if error return immediatly, else check the iterator is empty
     if block.is_error() {
@@ -31,26 +124,30 @@ pub fn parse<I: Iterator<Item = Token>>(tokens: &mut I) -> Tree<Name> {
     }
 }
 
-fn parse_block<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Tree<Name> {
+fn parse_block<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>) -> Tree<Name> {
     // read statements until '}'
-    fn parse_statements<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Vec<Tree<Name>> {
+    fn parse_statements<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>) -> Vec<Tree<Name>> {
         let mut stats = Vec::new();
         loop {
-            if let Some(Token { token, pos }) =  tokens.peek().cloned() {
+            if let Some(Token { token, .. }) =  tokens.peek().cloned() {
                 match token {
                     // end of block, return
                     TokenType::RightBrace => {
                         tokens.next();
                         return stats;
                     },
-                    // while can only happen in blocks, so we parse it here
-                    TokenType::While => {
+                    // loops can only happen in blocks, so we parse them here
+                    TokenType::While | TokenType::For => stats.push(parse_loop(tokens, None)),
+                    // labeled loop (like ''outer: while a { ... }')
+                    TokenType::Label(label) => {
                         tokens.next();
-                        let cond = parse_expr(tokens);
-                        stats.push(TreeType::While(Box::new(cond), Box::new(parse_block(tokens))).with_pos(pos));
+                        match tokens.next() {
+                            Some(Token { token: TokenType::Colon, .. }) => stats.push(parse_loop(tokens, Some(label))),
+                            tk => stats.push(TreeType::Error("expected ':' after label").with_pos(error_pos(tk)))
+                        }
                     },
                     // generic statement
-                    _ => stats.push(parse_expr(tokens))
+                    _ => stats.push(parse_statement(tokens))
                 }
             } else {
                 // we reached the end of the stream before the end of the block
@@ -76,7 +173,7 @@ fn parse_block<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Tree<Name
             tokens.next();
             block_from_vec(parse_statements(tokens), pos)
         } else if !FORCE_BLOCK_BRACES {
-            block_from_expr(parse_expr(tokens), pos)
+            block_from_expr(parse_statement(tokens), pos)
         } else {
             TreeType::Error("expected '{'").with_pos(pos)
         }
@@ -85,12 +182,34 @@ fn parse_block<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Tree<Name
     }
 }
 
+// doc comments are attached to the definition that follows them, and ignored anywhere else
+fn parse_statement<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>) -> Tree<Name> {
+    let doc = tokens.take_doc();
+    let stat = parse_expr(tokens);
+    let pos = stat.position().clone();
+    match stat.tree_type {
+        TreeType::Def(name, rhs, _, hoisted) => TreeType::Def(name, rhs, doc, hoisted).with_pos(pos),
+        tree_type => tree_type.with_pos(pos)
+    }
+}
+
 // parse a 'simple' expression (without binops)
-fn parse_simple_expr<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Tree<Name> {        
+fn parse_simple_expr<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>) -> Tree<Name> {        
     if let Some(Token { token: TokenType::LeftBrace, .. }) = tokens.peek().cloned() {
         return parse_block(tokens);
     }
 
+    // prefix operators bind tighter than binary operators, but looser than calls and indexing ('-f(x)' negates the result)
+    if let Some(Token { token: TokenType::Minus, .. }) | Some(Token { token: TokenType::Plus, .. }) | Some(Token { token: TokenType::Not, .. }) = tokens.peek() {
+        let Token { token, pos } = tokens.next().unwrap();
+        let operand = Box::new(parse_simple_expr(tokens));
+        return match token {
+            TokenType::Minus => TreeType::Neg(operand),
+            TokenType::Plus => TreeType::Pos(operand),
+            _ => TreeType::Not(operand)
+        }.with_pos(pos);
+    }
+
     if let Some(Token { token, pos }) = tokens.next() {
         let expr = match token {
 
@@ -104,17 +223,51 @@ fn parse_simple_expr<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Tre
                     _ => TreeType::Ident(name)
                 },
 
-            // number
-            TokenType::NumLit(num) => 
-                if let Ok(num) = num.parse::<i64>() {
-                    TreeType::IntLit(num)
-                } else {
-                    TreeType::Error("expected integer number")
-                },
+            // numbers
+            TokenType::IntLit(num) => TreeType::IntLit(num),
+            TokenType::FloatLit(num) => TreeType::FloatLit(num),
 
             // string
             TokenType::StrLit(lit) => TreeType::StrLit(lit),
 
+            // interpolated string, each embedded expression is parsed on its own
+            TokenType::FmtStr(parts) => TreeType::Format(parts.into_iter().map(|part| match part {
+                FmtPart::Lit(lit) => (TreeType::StrLit(lit).with_pos(pos.clone()), None),
+                FmtPart::Expr(tokens, spec) => {
+                    let mut tokens = Tokens::new(tokens.into_iter());
+                    let expr = parse_expr(&mut tokens);
+                    match tokens.next() {
+                        Some(Token { pos, .. }) => (TreeType::Error("expected '}'").with_pos(pos), spec),
+                        None => (expr, spec)
+                    }
+                }
+            }).collect()),
+
+            // control flow, only allowed where there is something to leave
+            TokenType::Break => {
+                let label = match parse_jump_label(tokens, &pos) {
+                    Ok(label) => label,
+                    Err(err) => return err
+                };
+                let val = parse_jump_value(tokens, &pos);
+                if tokens.in_loop() { TreeType::Break(label, Box::new(val)) } else { TreeType::Error("'break' outside of a loop") }
+            },
+            TokenType::Continue => {
+                let label = match parse_jump_label(tokens, &pos) {
+                    Ok(label) => label,
+                    Err(err) => return err
+                };
+                if tokens.in_loop() { TreeType::Continue(label) } else { TreeType::Error("'continue' outside of a loop") }
+            },
+            TokenType::Return => {
+                let val = parse_jump_value(tokens, &pos);
+                if tokens.in_func { TreeType::Return(Box::new(val)) } else { TreeType::Error("'return' outside of a function") }
+            },
+
+            // boolean
+            TokenType::True => TreeType::BoolLit(true),
+            TokenType::False => TreeType::BoolLit(false),
+
             // parenthesised expression (like '(a + b)') or tuple
             TokenType::LeftPar => {
                 let tuple = parse_tuple(tokens);
@@ -125,6 +278,19 @@ fn parse_simple_expr<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Tre
                 }
             },
 
+            // list literal
+            TokenType::LeftBracket => {
+                let (elems, _) = parse_elems(tokens, &TokenType::RightBracket);
+                if let Some(Token { token: TokenType::RightBracket, .. }) = tokens.next() {
+                    TreeType::List(elems)
+                } else {
+                    TreeType::Error("expected ']'")
+                }
+            },
+
+            // map literal (like '#{ a: 1, "b": 2 }')
+            TokenType::HashLeftBrace => parse_map(tokens),
+
             // conditional branch
             TokenType::If => {
                 let cond = parse_expr(tokens);
@@ -140,45 +306,137 @@ fn parse_simple_expr<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Tre
                 TreeType::If(Box::new(cond), Box::new(thenp), Box::new(elsep))
             },
 
+            // pattern matching (like 'match p { (0, y) => y, _ => 0 }')
+            TokenType::Match => return parse_match(tokens, pos),
+
             // definition
-            TokenType::Let => {
-                if let Some(Token { token: TokenType::Ident(name), .. }) = tokens.next() {
+            TokenType::Let => match tokens.peek().cloned() {
+                Some(Token { token: TokenType::Ident(ref name), .. }) if name != "_" => {
+                    tokens.next();
                     if let Some(Token { token: TokenType::Assign, .. }) = tokens.next() {
-                        TreeType::Def(name, Box::new(parse_expr(tokens)))
+                        TreeType::Def(name.clone(), Box::new(parse_expr(tokens)), None, false)
+                    } else {
+                        TreeType::Error("expected '='")
+                    }
+                },
+                // destructuring (like 'let (a, [b, ..rest]) = f()')
+                tk => {
+                    let pattern = match parse_pattern(tokens) {
+                        Ok(Pattern::Rest(_)) => return TreeType::Error("'..' is only allowed in list patterns").with_pos(error_pos(tk)),
+                        Ok(pattern) => pattern,
+                        Err(err) => return err
+                    };
+                    if let Some(Token { token: TokenType::Assign, .. }) = tokens.next() {
+                        TreeType::DefPattern(pattern, Box::new(parse_expr(tokens)))
                     } else {
                         TreeType::Error("expected '='")
                     }
-                } else {
-                    TreeType::Error("expected identifier")
                 }
             },
 
+            // function declaration (like 'fn half(n) { n / 2 }'), it can be called from anywhere in its block
+            TokenType::Fn => {
+                let name = match tokens.next() {
+                    Some(Token { token: TokenType::Ident(name), .. }) => name,
+                    tk => return TreeType::Error("expected identifier after 'fn'").with_pos(error_pos(tk))
+                };
+                let params_pos = match tokens.next() {
+                    Some(Token { token: TokenType::LeftPar, pos }) => pos,
+                    tk => return TreeType::Error("expected '('").with_pos(error_pos(tk))
+                };
+                let (params, _) = parse_elems(tokens, &TokenType::RightPar);
+                match tokens.next() {
+                    Some(Token { token: TokenType::RightPar, .. }) => (),
+                    tk => return TreeType::Error("expected ',' or ')'").with_pos(error_pos(tk))
+                }
+                let params = match to_params(TreeType::Tuple(params).with_pos(params_pos)) {
+                    Ok(params) => params,
+                    Err(err) => return err
+                };
+                match tokens.peek().cloned() {
+                    Some(Token { token: TokenType::LeftBrace, .. }) => (),
+                    tk => return TreeType::Error("expected '{'").with_pos(error_pos(tk))
+                }
+                let body = tokens.func_body(parse_block);
+                TreeType::Def(name, Box::new(TreeType::Func(params, Rc::new(body)).with_pos(pos.clone())), None, true)
+            },
+
+            // rest of a list, only valid in lambda parameters (like '[a, ..rest] => rest')
+            TokenType::DotDot => match tokens.peek().cloned() {
+                Some(Token { token: TokenType::Ident(name), pos: name_pos }) if same_line(&pos, &name_pos) => {
+                    tokens.next();
+                    TreeType::Rest(Some(name))
+                },
+                _ => TreeType::Rest(None)
+            },
+
+            // tokenizer error
+            TokenType::Error(err) => TreeType::Error(err),
+
             // parse error
             _ => TreeType::Error("expected expression or '('")
         };
 
         let mut expr = expr;
-        // if the expression is followed by a number of parenthesied expressions convert it to a call
-        while let Some(Token { token: TokenType::LeftPar, pos: call_pos }) = tokens.peek().cloned() {
+        // if the expression is followed by a number of parenthesied or bracketed expressions convert it to calls and indexings
+        while let Some(Token { token, pos: call_pos }) = tokens.peek().cloned() {
             // check the arg block is on the same line
             if !same_line(&pos, &call_pos) {
                 break;
             }
-                
-            fn parse_args<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Vec<Tree<Name>> {
-                // eat '(', saving the pos
-                let pos = tokens.next().unwrap().pos;
-                let elems = to_vec(parse_tuple(tokens).with_pos(pos));
-                match tokens.next() {
-                    Some(Token { token: TokenType::RightPar, pos }) => elems,
-                    tk => vec![TreeType::Error("expected expression or ')'").with_pos(error_pos(tk))],
-                }
+
+            if token == TokenType::LeftBracket {
+                tokens.next();
+                let index = parse_expr(tokens);
+                expr = match tokens.next() {
+                    Some(Token { token: TokenType::RightBracket, .. }) => TreeType::Index(Box::new(expr.with_pos(call_pos)), Box::new(index)),
+                    _ => TreeType::Error("expected ']'")
+                };
+                continue;
+            } else if token == TokenType::Dot {
+                // field access (like 'a.b') is sugar for 'a["b"]'
+                tokens.next();
+                expr = match tokens.next() {
+                    Some(Token { token: TokenType::Ident(name), pos: name_pos }) => TreeType::Index(Box::new(expr.with_pos(call_pos)), Box::new(TreeType::StrLit(name).with_pos(name_pos))),
+                    _ => TreeType::Error("expected identifier after '.'")
+                };
+                continue;
+            } else if token != TokenType::LeftPar {
+                break;
             }
 
-            let args = parse_args(tokens);
-            expr = TreeType::Call(Box::new(expr.with_pos(call_pos)), args)
+            tokens.next();
+            expr = match parse_args(tokens) {
+                Ok((args, named)) => TreeType::Call(Box::new(expr.with_pos(call_pos)), args, named),
+                Err(err) => return err
+            }
         } 
 
+        // indexed assignment (like 'a[1] = b')
+        if let TreeType::Index(..) = expr {
+            if let Some(&Token { token: TokenType::Assign, .. }) = tokens.peek() {
+                tokens.next();
+                let rhs = parse_expr(tokens);
+                expr = match expr {
+                    TreeType::Index(ref lhs, _) if !is_assignable(lhs) => TreeType::Error("expected identifier or index before '='"),
+                    TreeType::Index(lhs, index) => TreeType::IndexAssign(lhs, index, Box::new(rhs)),
+                    _ => unreachable!()
+                };
+            }
+        }
+
+        // compound assignment (like 'a += 1' or 'a[i] *= 2')
+        if let Some(op) = tokens.peek().and_then(|t| assign_op(&t.token)) {
+            tokens.next();
+            let target = expr.with_pos(pos.clone());
+            let rhs = parse_expr(tokens);
+            expr = if is_assignable(&target) {
+                TreeType::OpAssign(op, Box::new(target), Box::new(rhs))
+            } else {
+                TreeType::Error("expected identifier or index before compound assignment")
+            };
+        }
+
         expr.with_pos(pos)
 
     } else {
@@ -187,74 +445,312 @@ fn parse_simple_expr<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Tre
 }
 
 // parse a complex expression, (with binops and stuff)
-fn parse_expr<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Tree<Name> {
-    fn fetch_op<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> Option<Token> {
-        if is_bin_op(tokens.peek()) {
-            tokens.next()
+fn parse_expr<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>) -> Tree<Name> {
+    parse_bin_expr(tokens, 0)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Assoc {
+    Left,
+    Right
+}
+
+// binding power and associativity of binary operators, higher binds tighter
+// '=>' binds the loosest and to the right, so 'x => y => x + y' is a function returning a function
+fn bin_op_precedence(token: &TokenType) -> Option<(u8, Assoc)> {
+    match *token {
+        TokenType::FatArrow => Some((0, Assoc::Right)),
+        TokenType::Or => Some((1, Assoc::Left)),
+        TokenType::And => Some((2, Assoc::Left)),
+        TokenType::Eq | TokenType::Neq => Some((3, Assoc::Left)),
+        TokenType::Lt | TokenType::Le | TokenType::Gt | TokenType::Ge => Some((4, Assoc::Left)),
+        TokenType::DotDot | TokenType::DotDotEq => Some((5, Assoc::Left)),
+        TokenType::Plus | TokenType::Minus => Some((6, Assoc::Left)),
+        TokenType::Star | TokenType::Slash | TokenType::Percent => Some((7, Assoc::Left)),
+        _ => None
+    }
+}
+
+// precedence climbing: parses operands joined by operators binding at least as tight as min_prec
+fn parse_bin_expr<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>, min_prec: u8) -> Tree<Name> {
+    let mut lhs = parse_simple_expr(tokens);
+    while let Some((prec, assoc)) = tokens.peek().and_then(|t| bin_op_precedence(&t.token)) {
+        if prec < min_prec {
+            break;
+        }
+        // a '-' or '+' starting a line starts a new statement (like '-x'), the other operators can't be prefixes
+        let line = tokens.line;
+        if let Some(Token { token: TokenType::Minus, pos }) | Some(Token { token: TokenType::Plus, pos }) = tokens.peek() {
+            if pos.line() != line {
+                break;
+            }
+        }
+        let op = tokens.next().unwrap();
+        // the rhs of a left associative operator stops at the next operator of the same level
+        let next_prec = if assoc == Assoc::Left { prec + 1 } else { prec };
+        let rhs = if op.token == TokenType::FatArrow {
+            tokens.func_body(|tokens| parse_bin_expr(tokens, next_prec))
+        } else {
+            parse_bin_expr(tokens, next_prec)
+        };
+        lhs = create_bin_op(op, lhs, rhs);
+    }
+    lhs
+}
+
+// parse the optional value after 'break' or 'return', which has to start on the same line
+fn parse_jump_value<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>, pos: &Position) -> Tree<Name> {
+    match tokens.peek().cloned() {
+        Some(Token { token: TokenType::RightBrace, .. }) | Some(Token { token: TokenType::RightPar, .. }) |
+        Some(Token { token: TokenType::RightBracket, .. }) | Some(Token { token: TokenType::Comma, .. }) | None => TreeType::Empty.with_pos(pos.clone()),
+        Some(Token { pos: ref next_pos, .. }) if !same_line(pos, next_pos) => TreeType::Empty.with_pos(pos.clone()),
+        _ => parse_expr(tokens)
+    }
+}
+
+// parse the subject and arms of a match, after 'match'
+fn parse_match<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>, pos: Position) -> Tree<Name> {
+    let expr = parse_expr(tokens);
+    match tokens.next() {
+        Some(Token { token: TokenType::LeftBrace, .. }) => (),
+        tk => return TreeType::Error("expected '{' after match subject").with_pos(error_pos(tk))
+    }
+
+    let mut arms = Vec::new();
+    loop {
+        if let Some(Token { token: TokenType::RightBrace, .. }) = tokens.peek() {
+            tokens.next();
+            return TreeType::Match(Box::new(expr), arms).with_pos(pos);
+        }
+
+        let arm_pos = error_pos(tokens.peek().cloned());
+        let pattern = match parse_pattern(tokens) {
+            Ok(Pattern::Rest(_)) => return TreeType::Error("'..' is only allowed in list patterns").with_pos(arm_pos),
+            Ok(pattern) => pattern,
+            Err(err) => return err
+        };
+
+        // the guard binds tighter than '=>', which would make it a function
+        let guard = if let Some(Token { token: TokenType::If, .. }) = tokens.peek() {
+            tokens.next();
+            Some(parse_bin_expr(tokens, 1))
         } else {
             None
+        };
+
+        match tokens.next() {
+            Some(Token { token: TokenType::FatArrow, .. }) => (),
+            tk => return TreeType::Error("expected '=>' after pattern").with_pos(error_pos(tk))
+        }
+        arms.push((pattern, guard, parse_expr(tokens)));
+
+        if let Some(Token { token: TokenType::Comma, .. }) = tokens.peek() {
+            tokens.next();
         }
     }
+}
 
-    fn is_bin_op(token: Option<&Token>) -> bool {
-        if let Some(token) = token {
-            match &token.token {
-                &TokenType::FatArrow => true,
-                &TokenType::Eq | &TokenType::Neq => true,
-                &TokenType::Plus | &TokenType::Minus | &TokenType::Star | &TokenType::Slash => true,
-                _ => false
+// parse a pattern, errors are returned as error trees
+fn parse_pattern<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>) -> Result<Pattern<Name>, Tree<Name>> {
+    match tokens.next() {
+        Some(Token { token: TokenType::Ident(name), .. }) =>
+            Ok(if name == "_" { Pattern::Wildcard } else { Pattern::Bind(name) }),
+
+        Some(Token { token: TokenType::IntLit(num), pos }) => Ok(Pattern::Lit(Box::new(TreeType::IntLit(num).with_pos(pos)))),
+        Some(Token { token: TokenType::FloatLit(num), pos }) => Ok(Pattern::Lit(Box::new(TreeType::FloatLit(num).with_pos(pos)))),
+        Some(Token { token: TokenType::StrLit(lit), pos }) => Ok(Pattern::Lit(Box::new(TreeType::StrLit(lit).with_pos(pos)))),
+        Some(Token { token: TokenType::True, pos }) => Ok(Pattern::Lit(Box::new(TreeType::BoolLit(true).with_pos(pos)))),
+        Some(Token { token: TokenType::False, pos }) => Ok(Pattern::Lit(Box::new(TreeType::BoolLit(false).with_pos(pos)))),
+        // negative numbers
+        Some(Token { token: TokenType::Minus, pos }) => match tokens.next() {
+            Some(Token { token: TokenType::IntLit(num), .. }) => Ok(Pattern::Lit(Box::new(TreeType::IntLit(-num).with_pos(pos)))),
+            Some(Token { token: TokenType::FloatLit(num), .. }) => Ok(Pattern::Lit(Box::new(TreeType::FloatLit(-num).with_pos(pos)))),
+            tk => Err(TreeType::Error("expected number after '-' in pattern").with_pos(error_pos(tk)))
+        },
+
+        // like expressions, a single parenthesised pattern without trailing comma is not a tuple
+        Some(Token { token: TokenType::LeftPar, pos }) => {
+            let (mut elems, trailing_comma) = parse_pattern_elems(tokens, TokenType::RightPar)?;
+            if elems.iter().any(|e| matches!(e, Pattern::Rest(_))) {
+                Err(TreeType::Error("'..' is only allowed in list patterns").with_pos(pos))
+            } else if FOLD_TUPLE_1 && elems.len() == 1 && !trailing_comma {
+                Ok(elems.pop().unwrap())
+            } else {
+                Ok(Pattern::Tuple(elems))
             }
-        } else {
-            false
-        } 
+        },
+        Some(Token { token: TokenType::LeftBracket, pos }) => {
+            let (elems, _) = parse_pattern_elems(tokens, TokenType::RightBracket)?;
+            if elems.iter().filter(|e| matches!(e, Pattern::Rest(_))).count() > 1 {
+                Err(TreeType::Error("only one '..' is allowed in a list pattern").with_pos(pos))
+            } else {
+                Ok(Pattern::List(elems))
+            }
+        },
+        // rest of a list (like '..' or '..tail')
+        Some(Token { token: TokenType::DotDot, .. }) => match tokens.peek().cloned() {
+            Some(Token { token: TokenType::Ident(name), .. }) => {
+                tokens.next();
+                Ok(Pattern::Rest(Some(name)))
+            },
+            _ => Ok(Pattern::Rest(None))
+        },
+
+        tk => Err(TreeType::Error("expected pattern").with_pos(error_pos(tk)))
     }
+}
 
-    fn bin_op_associativity(tk: &Token) -> i32 {
-        match &tk.token {
-            &TokenType::FatArrow => 0,
-            &TokenType::Eq | &TokenType::Neq => 1,
-            &TokenType::Plus | &TokenType::Minus => 2,
-            &TokenType::Star | &TokenType::Slash => 3,
-            _ => unreachable!()
+// parse comma separated patterns up to and including the end token, also returns whether there was a trailing comma
+fn parse_pattern_elems<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>, end: TokenType) -> Result<(Vec<Pattern<Name>>, bool), Tree<Name>> {
+    let mut elems = Vec::new();
+    loop {
+        if tokens.peek().map(|t| t.token == end) == Some(true) {
+            tokens.next();
+            return Ok((elems, true));
+        }
+        elems.push(parse_pattern(tokens)?);
+        match tokens.next() {
+            Some(Token { token: TokenType::Comma, .. }) => (),
+            Some(Token { ref token, .. }) if *token == end => return Ok((elems, false)),
+            tk => return Err(TreeType::Error("expected ',' or end of pattern").with_pos(error_pos(tk)))
         }
     }
+}
 
-    let mut left = parse_simple_expr(tokens);
-    let mut op = if let Some(op) = fetch_op(tokens) {
-        op
-    } else {
-        return left;
-    };
-    let mut middle = parse_simple_expr(tokens);
+// parse the optional label after 'break' or 'continue', which has to name an enclosing loop
+fn parse_jump_label<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>, pos: &Position) -> Result<Option<Name>, Tree<Name>> {
+    match tokens.peek().cloned() {
+        Some(Token { token: TokenType::Label(label), pos: label_pos }) if same_line(pos, &label_pos) => {
+            tokens.next();
+            if tokens.has_label(&label) {
+                Ok(Some(label))
+            } else {
+                Err(TreeType::Error("unknown loop label").with_pos(label_pos))
+            }
+        },
+        _ => Ok(None)
+    }
+}
+
+// parse a while or for loop
+fn parse_loop<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>, label: Option<Name>) -> Tree<Name> {
+    match tokens.next() {
+        Some(Token { token: TokenType::While, pos }) => {
+            let cond = parse_expr(tokens);
+            let body = tokens.loop_body(label.clone());
+            TreeType::While(Box::new(cond), Box::new(body), label).with_pos(pos)
+        },
+        // for loop (like 'for (k, v) in entries { ... }')
+        Some(Token { token: TokenType::For, pos }) => {
+            let bind_pos = error_pos(tokens.peek().cloned());
+            let bind = match parse_pattern(tokens) {
+                Ok(Pattern::Rest(_)) => return TreeType::Error("'..' is only allowed in list patterns").with_pos(bind_pos),
+                Ok(bind) => bind,
+                Err(err) => return err
+            };
+            match tokens.next() {
+                Some(Token { token: TokenType::In, .. }) => {
+                    let iter = parse_expr(tokens);
+                    let body = tokens.loop_body(label.clone());
+                    TreeType::For(Box::new(bind), Box::new(iter), Box::new(body), label).with_pos(pos)
+                },
+                tk => TreeType::Error("expected 'in'").with_pos(error_pos(tk))
+            }
+        },
+        tk => TreeType::Error("expected 'while' or 'for' after label").with_pos(error_pos(tk))
+    }
+}
+
+// parse the comma separated 'key: value' entries of a map literal, until '}'
+fn parse_map<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>) -> TreeType<Name> {
+    let mut entries = Vec::new();
     loop {
-        let op_2 = if let Some(op) = fetch_op(tokens) {
-            op
-        } else {
-            return create_bin_op(op, left, middle);
+        let key = match tokens.peek().cloned() {
+            Some(Token { token: TokenType::RightBrace, .. }) => {
+                tokens.next();
+                return TreeType::Map(entries);
+            },
+            // a lone identifier key is sugar for a string, anything else is computed (like 'a + 1: 2')
+            Some(Token { token: TokenType::Ident(_), .. }) => {
+                let key = parse_expr(tokens);
+                let pos = key.position().clone();
+                match key.tree_type {
+                    TreeType::Ident(name) => TreeType::StrLit(name).with_pos(pos),
+                    tree_type => tree_type.with_pos(pos)
+                }
+            },
+            _ => parse_expr(tokens)
         };
-        let right = parse_simple_expr(tokens);
-        if bin_op_associativity(&op) < bin_op_associativity(&op_2) {
-            middle = create_bin_op(op_2, middle, right);
+
+        if let Some(Token { token: TokenType::Colon, .. }) = tokens.next() {
+            entries.push((key, parse_expr(tokens)));
         } else {
-            left = create_bin_op(op, left, middle);
-            middle = right;
-            op = op_2;
+            return TreeType::Error("expected ':'");
+        }
+
+        match tokens.next() {
+            Some(Token { token: TokenType::Comma, .. }) => (),
+            Some(Token { token: TokenType::RightBrace, .. }) => return TreeType::Map(entries),
+            _ => return TreeType::Error("expected ',' or '}'")
         }
     }
 }
 
-// parse a list of comma separated trees
-fn parse_tuple<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> TreeType<Name> {
-    fn is_end(token: &Option<Token>) -> bool {
-        if let &Some(ref token) = token {
-            match token.token {
-                TokenType::RightPar => true,
-                _ => false
+// parse the arguments of a call until ')', named arguments (like 'scale: 3') come after the positional ones
+fn parse_args<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>) -> Result<Args, Tree<Name>> {
+    let mut args = Vec::new();
+    let mut named: Vec<NamedArg<Name>> = Vec::new();
+    loop {
+        if let Some(Token { token: TokenType::RightPar, .. }) = tokens.peek() {
+            tokens.next();
+            return Ok((args, named));
+        }
+
+        let arg = parse_expr(tokens);
+        let arg_pos = arg.position().clone();
+        if let Some(Token { token: TokenType::Colon, .. }) = tokens.peek() {
+            tokens.next();
+            let name = match arg.tree_type {
+                TreeType::Ident(name) => name,
+                _ => return Err(TreeType::Error("expected identifier before ':'").with_pos(arg_pos))
+            };
+            if named.iter().any(|(n, _)| *n == name) {
+                return Err(TreeType::Error("duplicate named argument").with_pos(arg_pos));
             }
+            named.push((name, parse_expr(tokens)));
+        } else if !named.is_empty() {
+            return Err(TreeType::Error("positional argument after named arguments").with_pos(arg_pos));
         } else {
-            true
-        } 
+            args.push(arg);
+        }
+
+        match tokens.next() {
+            Some(Token { token: TokenType::Comma, .. }) => (),
+            Some(Token { token: TokenType::RightPar, .. }) => return Ok((args, named)),
+            tk => return Err(TreeType::Error("expected ',' or ')'").with_pos(error_pos(tk)))
+        }
+    }
+}
+
+// parse a parenthesised list of comma separated trees
+fn parse_tuple<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>) -> TreeType<Name> {
+    // a trailing comma forces a tuple, even with a single element
+    match parse_elems(tokens, &TokenType::RightPar) {
+        (elems, true) => TreeType::Tuple(elems),
+        (elems, false) => tuple_from_vec(elems)
     }
+}
+
+// parse a list of comma separated trees until the end token (which is not consumed)
+// also returns whether the list ended with a trailing comma
+fn parse_elems<I: Iterator<Item = Token>>(tokens: &mut Tokens<I>, end: &TokenType) -> (Vec<Tree<Name>>, bool) {
+    let is_end = |token: &Option<Token>| {
+        if let &Some(ref token) = token {
+            token.token == *end
+        } else {
+            true
+        }
+    };
 
     fn is_comma(token: &Option<Token>) -> bool {
         if let Some(Token { token: TokenType::Comma, .. }) = *token {
@@ -266,24 +762,25 @@ fn parse_tuple<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> TreeType<
 
     let mut elems = Vec::new();
     if is_end(&tokens.peek().cloned()) {
-        return tuple_from_vec(elems);
+        return (elems, false);
     } else {
         elems.push(parse_expr(tokens));
     }
 
     loop {
         match tokens.peek().cloned() {
-            ref t if is_end(&t) => return tuple_from_vec(elems),
-            ref t if is_comma(&t) => {
+            ref t if is_end(t) => return (elems, false),
+            ref t if is_comma(t) => {
                 tokens.next();
                 if is_end(&tokens.peek().cloned()) {
-                    return TreeType::Tuple(elems);
+                    return (elems, true);
                 }
                 elems.push(parse_expr(tokens));
             },
             t => {
-                elems.push(TreeType::Error("expected ',', or ')''").with_pos(error_pos(t)));
-                return tuple_from_vec(elems);
+                let err = if *end == TokenType::RightPar { "expected ',', or ')''" } else { "expected ',', or ']'" };
+                elems.push(TreeType::Error(err).with_pos(error_pos(t)));
+                return (elems, false);
             } 
         }  
     }
@@ -295,14 +792,26 @@ fn parse_tuple<I: Iterator<Item = Token>>(tokens: &mut Peekable<I>) -> TreeType<
 
 fn create_bin_op(op: Token, lhs: Tree<Name>, rhs: Tree<Name>) -> Tree<Name> {
     match op.token {
-        TokenType::FatArrow => TreeType::Func(to_vec(lhs), Rc::new(rhs)),
+        TokenType::FatArrow => match to_params(lhs) {
+            Ok(params) => TreeType::Func(params, Rc::new(rhs)),
+            Err(err) => return err
+        },
+        TokenType::Or => TreeType::Or(Box::new(lhs), Box::new(rhs)),
+        TokenType::And => TreeType::And(Box::new(lhs), Box::new(rhs)),
         TokenType::Eq => TreeType::Eq(Box::new(lhs), Box::new(rhs)),
         TokenType::Neq => TreeType::Neq(Box::new(lhs), Box::new(rhs)),
+        TokenType::Lt => TreeType::Lt(Box::new(lhs), Box::new(rhs)),
+        TokenType::Le => TreeType::Le(Box::new(lhs), Box::new(rhs)),
+        TokenType::Gt => TreeType::Gt(Box::new(lhs), Box::new(rhs)),
+        TokenType::Ge => TreeType::Ge(Box::new(lhs), Box::new(rhs)),
         TokenType::Plus => TreeType::Add(Box::new(lhs), Box::new(rhs)),
         TokenType::Minus => TreeType::Sub(Box::new(lhs), Box::new(rhs)),
         TokenType::Star => TreeType::Mul(Box::new(lhs), Box::new(rhs)),
         TokenType::Slash => TreeType::Div(Box::new(lhs), Box::new(rhs)),
-        _ => TreeType::Error("expected '+', '-', '*', '/', '==', '!=' or '=>'")
+        TokenType::Percent => TreeType::Mod(Box::new(lhs), Box::new(rhs)),
+        TokenType::DotDot => TreeType::Range(Box::new(lhs), Box::new(rhs), false),
+        TokenType::DotDotEq => TreeType::Range(Box::new(lhs), Box::new(rhs), true),
+        _ => TreeType::Error("expected binary operator or '=>'")
     }.with_pos(op.pos)
 }
 
@@ -321,6 +830,78 @@ fn to_vec(tpl: Tree<Name>) -> Vec<Tree<Name>> {
     }
 }
 
+// only identifiers and indexings of assignable trees can be assigned to
+fn is_assignable(tree: &Tree<Name>) -> bool {
+    match tree.tree_type {
+        TreeType::Ident(_) => true,
+        TreeType::Index(ref lhs, _) => is_assignable(lhs),
+        _ => false
+    }
+}
+
+// convert the lhs of '=>' to parameters, identifiers can have defaults (like 'b = 2') and the last one can be a rest
+fn to_params(lhs: Tree<Name>) -> Result<Vec<Param<Name>>, Tree<Name>> {
+    let mut params: Vec<Param<Name>> = Vec::new();
+    for tree in to_vec(lhs) {
+        let pos = tree.position().clone();
+        if let Some((Pattern::Rest(_), _)) = params.last() {
+            return Err(TreeType::Error("the rest parameter must be the last one").with_pos(pos));
+        }
+        let param = match tree.tree_type {
+            TreeType::Assign(name, default) => (to_pattern(TreeType::Ident(name).with_pos(pos.clone()))?, Some(*default)),
+            _ => (to_pattern(tree)?, None)
+        };
+        if param.1.is_none() && !matches!(param.0, Pattern::Rest(_)) && params.iter().any(|p| p.1.is_some()) {
+            return Err(TreeType::Error("expected default value after a parameter with default").with_pos(pos));
+        }
+        params.push(param);
+    }
+    Ok(params)
+}
+
+// convert a lambda parameter, parsed as an expression, to a pattern
+fn to_pattern(tree: Tree<Name>) -> Result<Pattern<Name>, Tree<Name>> {
+    let pos = tree.position().clone();
+    match tree.tree_type {
+        TreeType::Ident(ref name) if name == "_" => Ok(Pattern::Wildcard),
+        TreeType::Ident(name) => Ok(Pattern::Bind(name)),
+        TreeType::IntLit(_) | TreeType::FloatLit(_) | TreeType::StrLit(_) | TreeType::BoolLit(_) => Ok(Pattern::Lit(Box::new(tree))),
+        // negative numbers, the sign is folded into the literal like in match patterns
+        TreeType::Neg(operand) => match operand.tree_type {
+            TreeType::IntLit(num) => Ok(Pattern::Lit(Box::new(TreeType::IntLit(-num).with_pos(pos)))),
+            TreeType::FloatLit(num) => Ok(Pattern::Lit(Box::new(TreeType::FloatLit(-num).with_pos(pos)))),
+            _ => Err(TreeType::Error("expected pattern before '=>'").with_pos(pos))
+        },
+        TreeType::Tuple(elems) => {
+            let elems = elems.into_iter().map(to_pattern).collect::<Result<Vec<_>, _>>()?;
+            if elems.iter().any(|e| matches!(e, Pattern::Rest(_))) {
+                return Err(TreeType::Error("'..' is only allowed in list patterns").with_pos(pos));
+            }
+            Ok(Pattern::Tuple(elems))
+        },
+        TreeType::List(elems) => {
+            let elems = elems.into_iter().map(to_pattern).collect::<Result<Vec<_>, _>>()?;
+            if elems.iter().filter(|e| matches!(e, Pattern::Rest(_))).count() > 1 {
+                return Err(TreeType::Error("only one '..' is allowed in a list pattern").with_pos(pos));
+            }
+            Ok(Pattern::List(elems))
+        },
+        TreeType::Rest(name) => Ok(Pattern::Rest(name)),
+        TreeType::Error(_) => Err(tree),
+        _ => Err(TreeType::Error("expected pattern before '=>'").with_pos(pos))
+    }
+}
+
+fn assign_op(token: &TokenType) -> Option<AssignOp> {
+    match *token {
+        TokenType::PlusAssign => Some(AssignOp::Add),
+        TokenType::MinusAssign => Some(AssignOp::Sub),
+        TokenType::StarAssign => Some(AssignOp::Mul),
+        TokenType::SlashAssign => Some(AssignOp::Div),
+        _ => None
+    }
+}
+
 fn eof_error() -> Tree<Name> {
     TreeType::Error("unexpected EOF").with_pos(Position::eof())
 }
@@ -0,0 +1,95 @@
+use std::io::{self, Write};
+
+use eval::*;
+use value::*;
+use error::*;
+use map::*;
+
+// installs the built-in functions in env
+pub fn install(env: &mut Env) -> Result<(), RuntimeErrorType> {
+    // io
+    env.def_native("print", 1, |args| {
+        print!("{}", args[0]);
+        io::stdout().flush().ok();
+        Ok(Value::Unit)
+    })?;
+    env.def_native("println", 1, |args| {
+        println!("{}", args[0]);
+        Ok(Value::Unit)
+    })?;
+
+    // conversions
+    env.def_native("str", 1, |args| Ok(Value::Str(args[0].clone().to_str())))?;
+    env.def_native("num", 1, |args| to_num(&args[0]))?;
+    env.def_native("int", 1, |args| {
+        match to_num(&args[0])? {
+            // truncates toward zero
+            Value::Float(n) if n.is_finite() && n.abs() < i64::MAX as f64 => Ok(Value::Int(n as i64)),
+            Value::Float(_) => Err(RuntimeErrorType::IntegerOverflow),
+            n => Ok(n)
+        }
+    })?;
+    env.def_native("float", 1, |args| Ok(Value::Float(to_num(&args[0])?.to_float()?)))?;
+
+    // introspection
+    env.def_native("type_of", 1, |args| Ok(Value::Str(String::from(args[0].type_name()))))?;
+    env.def_native("len", 1, |args| {
+        match args[0] {
+            Value::Str(ref s) => Ok(Value::Int(s.chars().count() as i64)),
+            Value::Tuple(ref elems) | Value::List(ref elems) => Ok(Value::Int(elems.len() as i64)),
+            Value::Map(ref map) => Ok(Value::Int(map.len() as i64)),
+            Value::Range(start, end, inclusive) if end > start || (inclusive && end == start) => {
+                let len = end.checked_sub(start).and_then(|len| len.checked_add(inclusive as i64));
+                Ok(Value::Int(len.ok_or(RuntimeErrorType::IntegerOverflow)?))
+            },
+            Value::Range(..) => Ok(Value::Int(0)),
+            ref x => Err(RuntimeErrorType::TypeMismatch(format!("{:?} has no length", x)))
+        }
+    })?;
+
+    // maps, values are never modified in place so remove returns the updated map
+    env.def_native("keys", 1, |args| Ok(Value::List(to_map(&args[0])?.iter().map(|(k, _)| k.to_value()).collect())))?;
+    env.def_native("values", 1, |args| Ok(Value::List(to_map(&args[0])?.iter().map(|(_, v)| v.clone()).collect())))?;
+    env.def_native("has_key", 2, |args| Ok(Value::Bool(to_map(&args[0])?.contains_key(&Key::from_value(&args[1])?))))?;
+    env.def_native("remove", 2, |args| {
+        let mut map = to_map(&args[0])?.clone();
+        map.remove(&Key::from_value(&args[1])?);
+        Ok(Value::Map(map))
+    })?;
+
+    // assertions
+    env.def_native("assert", 1, |args| {
+        if args[0].clone().to_bool()? {
+            Ok(Value::Unit)
+        } else {
+            Err(RuntimeErrorType::AssertionFailed(format!("{:?}", args[0])))
+        }
+    })?;
+    env.def_native("assert_eq", 2, |args| {
+        if args[0] == args[1] {
+            Ok(Value::Unit)
+        } else {
+            Err(RuntimeErrorType::AssertionFailed(format!("{:?} != {:?}", args[0], args[1])))
+        }
+    })?;
+
+    Ok(())
+}
+
+fn to_map(val: &Value) -> Result<&Map, RuntimeErrorType> {
+    match *val {
+        Value::Map(ref map) => Ok(map),
+        ref x => Err(RuntimeErrorType::TypeMismatch(format!("{:?} is not a map", x)))
+    }
+}
+
+// numbers are returned as is, strings are parsed as an int if possible, or as a float
+fn to_num(val: &Value) -> Result<Value, RuntimeErrorType> {
+    match *val {
+        Value::Int(_) | Value::Float(_) => Ok(val.clone()),
+        Value::Str(ref s) => s.parse().map(Value::Int)
+            .or_else(|_| s.parse().map(Value::Float))
+            .map_err(|_| RuntimeErrorType::TypeMismatch(format!("{:?} is not a number", s))),
+        ref x => Err(RuntimeErrorType::TypeMismatch(format!("{:?} is not a number", x)))
+    }
+}
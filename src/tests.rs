@@ -1,6 +1,12 @@
+use std::rc::Rc;
+
 use tree::*;
 use tokenizer::*;
 use parser;
+use eval::*;
+use value::*;
+use error::*;
+use interpreter::*;
 
 
 type Name = String;
@@ -27,6 +33,16 @@ fn error(t: &Tree<Name>) -> Option<&Tree<Name>> {
     err
 }
 
+fn eval_str(input: &str) -> Value {
+    let tree = parse_no_error(input);
+    eval(&tree, &mut Env::new()).unwrap()
+}
+
+fn eval_error(input: &str) -> RuntimeError {
+    let tree = parse_no_error(input);
+    eval(&tree, &mut Env::new()).unwrap_err()
+}
+
 fn is_error(input: &str) -> bool {
     error(&parse(input)).is_some()
 }
@@ -120,6 +136,464 @@ fn parse_paren_expr() {
     assert!(is_add("(x + 4)"));
 }
 
+#[test]
+fn parse_ident() {
+    match parse_no_error("_foo_2").as_tree_type() {
+        TreeType::Ident(name) => assert_eq!(name, "_foo_2"),
+        t => panic!("unexpected tree: {:?}", t)
+    }
+}
+
+#[test]
+fn parse_list() {
+    match parse_no_error("[1, x, ]").as_tree_type() {
+        TreeType::List(e) => assert_eq!(e.len(), 2),
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    match parse_no_error("a[1][2] = 3").as_tree_type() {
+        TreeType::IndexAssign(lhs, ..) => assert!(matches!(lhs.tree_type, TreeType::Index(..))),
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert!(!is_error("{ let x = a\n[1, 2] }"));
+    assert!(is_error("[1, 2"));
+    assert!(is_error("f()[0] = 1"));
+}
+
+#[test]
+fn parse_map() {
+    match parse_no_error("#{ a: 1, \"b\": 2, 3: x, }").as_tree_type() {
+        TreeType::Map(e) => assert_eq!(e.len(), 3),
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert!(matches!(parse_no_error("a.b.c").as_tree_type(), TreeType::Index(..)));
+    assert!(matches!(parse_no_error("a.b = 1").as_tree_type(), TreeType::IndexAssign(..)));
+    assert!(is_error("#{ a 1 }"));
+    // only a lone identifier is a string key
+    match parse_no_error("#{ a + 1: 2, b: 3 }").as_tree_type() {
+        TreeType::Map(e) => {
+            assert!(matches!(e[0].0.tree_type, TreeType::Add(..)));
+            assert_eq!(e[1].0.tree_type, TreeType::StrLit("b".to_string()));
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert!(is_error("a.1"));
+}
+
+#[test]
+fn parse_num_literals() {
+    fn lit(input: &str) -> TreeType<Name> {
+        parse_no_error(input).as_tree_type()
+    }
+    assert_eq!(lit("42"), TreeType::IntLit(42));
+    assert_eq!(lit("1_000_000"), TreeType::IntLit(1000000));
+    assert_eq!(lit("0xFF"), TreeType::IntLit(255));
+    assert_eq!(lit("0b1010"), TreeType::IntLit(10));
+    assert_eq!(lit("0o17"), TreeType::IntLit(15));
+    assert_eq!(lit("2.75"), TreeType::FloatLit(2.75));
+    assert_eq!(lit("1e-3"), TreeType::FloatLit(0.001));
+    assert_eq!(lit("2.5E2"), TreeType::FloatLit(250.0));
+}
+
+#[test]
+fn parse_invalid_num_literals() {
+    fn error_col(input: &str) -> usize {
+        error(&parse(input)).unwrap().position().column()
+    }
+    assert_eq!(error_col("0b102"), 4);
+    assert_eq!(error_col("12ab"), 2);
+    assert_eq!(error_col("1e+"), 3);
+    assert_eq!(error_col("0x"), 2);
+    assert_eq!(error_col("99999999999999999999"), 0);
+}
+
+#[test]
+fn parse_str_literals() {
+    fn lit(input: &str) -> TreeType<Name> {
+        parse_no_error(input).as_tree_type()
+    }
+    assert_eq!(lit(r#""a\"b\n\t\\""#), TreeType::StrLit("a\"b\n\t\\".to_string()));
+    assert_eq!(lit(r#""\u{1F600}\u{e9}""#), TreeType::StrLit("\u{1F600}\u{e9}".to_string()));
+    assert_eq!(lit(r#"r"a\nb""#), TreeType::StrLit("a\\nb".to_string()));
+    assert_eq!(lit(r###"r#"say "hi""#"###), TreeType::StrLit("say \"hi\"".to_string()));
+    assert_eq!(lit("\"\"\"\nline 1\n\"line\" 2\"\"\""), TreeType::StrLit("line 1\n\"line\" 2".to_string()));
+    assert_eq!(lit("\"\""), TreeType::StrLit(String::new()));
+}
+
+#[test]
+fn parse_invalid_str_literals() {
+    fn error_col(input: &str) -> usize {
+        error(&parse(input)).unwrap().position().column()
+    }
+    assert_eq!(error_col(r#""ab\qc" + 1"#), 3);
+    assert_eq!(error_col(r#""a\u{110000}""#), 2);
+    assert_eq!(error_col(r#""a\u12""#), 2);
+    assert!(is_error("\"abc"));
+    assert!(is_error("r#\"abc\""));
+}
+
+#[test]
+fn parse_interpolation() {
+    match parse_no_error(r#""a {x + 1} b {y:>5.2}""#).as_tree_type() {
+        TreeType::Format(parts) => {
+            assert_eq!(parts.len(), 4);
+            assert!(matches!(parts[1].0.tree_type, TreeType::Add(..)));
+            assert_eq!(parts[3].1.as_ref().unwrap().width, Some(5));
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert_eq!(parse_no_error(r#""\{x\}""#).as_tree_type(), TreeType::StrLit("{x}".to_string()));
+    let shown = format!("{}", parse_no_error(r#""a\{ {x:<3} \"b\"""#));
+    assert_eq!(shown, r#""a\{ {x:<3} \"b\"""#);
+    assert_eq!(format!("{}", parse_no_error(&shown)), shown);
+    assert_eq!(parse_no_error(r#"r"{x}""#).as_tree_type(), TreeType::StrLit("{x}".to_string()));
+
+    let input = r#""{a} {b + ) }""#;
+    assert_eq!(error(&parse(input)).unwrap().position().column(), 10);
+    assert!(is_error(r#""{}""#));
+    assert!(is_error(r#""{a""#));
+    // the string ends before the interpolation does
+    assert_eq!(error(&parse(r#""{" + str(1) + "}""#)).unwrap().tree_type, TreeType::Error("unterminated interpolation"));
+    assert!(is_error(r#""{"a"}""#));
+    assert!(is_error(r#""{a:x}""#));
+}
+
+#[test]
+fn parse_comments() {
+    assert!(is_add("a + // comment\n b"));
+    assert!(is_add("a /* comment /* nested */ still comment */ + b"));
+    assert!(is_add("a + //// not a doc\n b"));
+    assert!(is_error("a /* unterminated /* */"));
+    assert_eq!(eval_str("{ let x = 1 // one\n /* let x = 2 */ x }"), Value::Int(1));
+}
+
+#[test]
+fn parse_doc_comments() {
+    let tree = parse_no_error("{\n /// adds one\n ///to x\n let f = x => x + 1\n /// ignored\n f(2)\n /// last\n let y = 3 }");
+    let mut docs = Vec::new();
+    tree.for_each(|t| if let Some(doc) = t.doc() {
+        docs.push((t.name().unwrap().clone(), doc.clone()));
+    });
+    assert_eq!(docs, vec![("f".to_string(), "adds one\nto x".to_string()), ("y".to_string(), "last".to_string())]);
+    match parse_no_error("/// doc\nlet a = 1").as_tree_type() {
+        TreeType::Def(_, _, doc, _) => assert_eq!(doc, Some("doc".to_string())),
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    // anywhere else they're skipped like regular comments
+    assert!(!is_error("{ /// loops\n while false { } }"));
+    assert!(!is_error("{ /// loops\n for x in [] { } }"));
+    assert!(!is_error("{ /// loops\n 'outer: while false { } }"));
+    assert_eq!(eval_str("{ let x = 1 /// trailing\n }"), Value::Int(1));
+    assert_eq!(eval_str("{ let m = #{ a: 1,\n /// the b\n b: 2 }\n m.b }"), Value::Int(2));
+    assert_eq!(eval_str("{ let x = 5\n /// doc\n * 2\n x }"), Value::Int(10));
+    parse_no_error("{ /// not f\n g(let f = 1) }").for_each(|t| assert_eq!(t.doc(), None));
+}
+
+#[test]
+fn parse_comparisons() {
+    match parse_no_error("a + 1 < b && c >= d || e").as_tree_type() {
+        TreeType::Or(lhs, _) => assert!(matches!(lhs.as_tree_type(), TreeType::And(..))),
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    match parse_no_error("a % 2 <= b").as_tree_type() {
+        TreeType::Le(lhs, _) => assert!(matches!(lhs.as_tree_type(), TreeType::Mod(..))),
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert!(is_error("a & b"));
+    assert!(is_error("a | b"));
+}
+
+#[test]
+fn parse_unary() {
+    match parse_no_error("-a * b").as_tree_type() {
+        TreeType::Mul(lhs, _) => assert!(matches!(lhs.as_tree_type(), TreeType::Neg(..))),
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    match parse_no_error("!f(x) && b").as_tree_type() {
+        TreeType::And(lhs, _) => match lhs.as_tree_type() {
+            TreeType::Not(expr) => assert!(matches!(expr.as_tree_type(), TreeType::Call(..))),
+            t => panic!("unexpected tree: {:?}", t)
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert!(matches!(parse_no_error("a - -b").as_tree_type(), TreeType::Sub(..)));
+    assert!(matches!(parse_no_error("+a").as_tree_type(), TreeType::Pos(..)));
+    assert!(is_error("-"));
+    // a '-' or '+' starting a line starts a new statement, the other operators continue the previous line
+    match parse_no_error("{ a\n -b\n +c }").as_tree_type() {
+        TreeType::Block(stats, expr) => {
+            assert_eq!(stats.len(), 2);
+            assert!(matches!(expr.as_tree_type(), TreeType::Pos(..)));
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert!(matches!(parse_no_error("a -\n b").as_tree_type(), TreeType::Sub(..)));
+    assert!(matches!(parse_no_error("a\n * b").as_tree_type(), TreeType::Mul(..)));
+}
+
+#[test]
+fn parse_compound_assign() {
+    match parse_no_error("a += 1 + 2").as_tree_type() {
+        TreeType::OpAssign(AssignOp::Add, lhs, rhs) => {
+            assert!(matches!(lhs.tree_type, TreeType::Ident(..)));
+            assert!(matches!(rhs.tree_type, TreeType::Add(..)));
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert!(matches!(parse_no_error("a[0].b *= 2").as_tree_type(), TreeType::OpAssign(AssignOp::Mul, ..)));
+    assert!(matches!(parse_no_error("a -= 1").as_tree_type(), TreeType::OpAssign(AssignOp::Sub, ..)));
+    assert!(matches!(parse_no_error("a /= 1").as_tree_type(), TreeType::OpAssign(AssignOp::Div, ..)));
+    assert!(is_error("f(a) += 1"));
+    assert!(is_error("1 += 1"));
+}
+
+#[test]
+fn parse_precedence() {
+    let cases = [
+        ("a + b * c", "(a + (b * c))"),
+        ("a * b + c", "((a * b) + c)"),
+        ("a - b - c", "((a - b) - c)"),
+        ("a / b / c", "((a / b) / c)"),
+        ("a - b + c - d", "(((a - b) + c) - d)"),
+        ("a * b % c / d", "(((a * b) % c) / d)"),
+        ("a == b + c * d - e", "(a == ((b + (c * d)) - e))"),
+        ("a + b == c * d", "((a + b) == (c * d))"),
+        ("a < b == c >= d", "((a < b) == (c >= d))"),
+        ("a == b != c", "((a == b) != c)"),
+        ("a + b < c * d", "((a + b) < (c * d))"),
+        ("a || b && c", "(a || (b && c))"),
+        ("a && b || c && d", "((a && b) || (c && d))"),
+        ("a || b || c", "((a || b) || c)"),
+        ("a && b == c", "(a && (b == c))"),
+        ("-a * b", "((-a) * b)"),
+        ("!a && b", "((!a) && b)"),
+        ("a - -b", "(a - (-b))"),
+        ("-f(a)[b]", "(-f(a, )[b])"),
+        ("(a + b) * c", "((a + b) * c)"),
+        ("a * (b + c)", "(a * (b + c))"),
+        ("a - (b - c)", "(a - (b - c))"),
+        ("x => x + 1", "((x, ) => (x + 1))"),
+        ("x => y => x * y", "((x, ) => ((y, ) => (x * y)))"),
+        ("(a, b) => a < b || a == b", "((a, b, ) => ((a < b) || (a == b)))"),
+        ("(x => x)(1) + 2", "(((x, ) => x)(1, ) + 2)"),
+        ("a + b + c + d + e + f * g * h - i", "((((((a + b) + c) + d) + e) + ((f * g) * h)) - i)"),
+    ];
+    for &(input, expected) in cases.iter() {
+        let shown = format!("{}", parse_no_error(input));
+        assert_eq!(shown, expected, "parsing {}", input);
+        // the output parses back to the same tree
+        assert_eq!(format!("{}", parse_no_error(&shown)), shown);
+    }
+}
+
+#[test]
+fn parse_for() {
+    match parse_no_error("{ for (k, v) in m { k } }").as_tree_type() {
+        TreeType::For(bind, iter, _, None) => {
+            assert_eq!(*bind, Pattern::Tuple(vec![Pattern::Bind("k".to_string()), Pattern::Bind("v".to_string())]));
+            assert!(matches!(iter.tree_type, TreeType::Ident(..)));
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert_eq!(format!("{}", parse_no_error("0..n + 1")), "(0..(n + 1))");
+    assert_eq!(format!("{}", parse_no_error("a..=b == c")), "((a..=b) == c)");
+    assert!(is_error("{ for f(x) in l { } }"));
+    assert!(is_error("{ for ..x in l { } }"));
+    assert!(is_error("{ for x l { } }"));
+}
+
+#[test]
+fn parse_jumps() {
+    match parse_no_error("{ while a { if b { break } else { continue } } }").as_tree_type() {
+        TreeType::While(_, body, None) => assert!(format!("{}", body).contains("break")),
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    match parse_no_error("x => { return x + 1 }").as_tree_type() {
+        TreeType::Func(_, body) => assert_eq!(format!("{}", body), "{\nreturn (x + 1)\n}"),
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert!(matches!(parse_no_error("{ for x in l { break x * 2 } }").as_tree_type(), TreeType::For(..)));
+    assert!(!is_error("{ while a { let f = () => { return 1 } } }"));
+    assert!(!is_error("{ for x in l { { if x { break } else { } } } }"));
+
+    let input = "{ let a = 1\n break }";
+    let tree = parse(input);
+    let err = error(&tree).unwrap();
+    assert!(matches!(err.tree_type, TreeType::Error("'break' outside of a loop")));
+    assert_eq!(err.position().line(), 1);
+    assert!(is_error("continue"));
+    assert!(is_error("return 1"));
+    assert!(is_error("{ while a { x }\n continue }"));
+    // functions can't leave the loop they are defined in
+    assert!(is_error("{ while a { let f = () => { break } } }"));
+}
+
+#[test]
+fn parse_labels() {
+    match parse_no_error("{ 'outer: for x in l { while b { break 'outer x } } }").as_tree_type() {
+        TreeType::For(_, _, body, Some(label)) => {
+            assert_eq!(label, "outer");
+            assert!(format!("{}", body).contains("break 'outer x"));
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert!(matches!(parse_no_error("{ 'a: while x { continue 'a } }").as_tree_type(), TreeType::While(_, _, Some(_))));
+    assert!(!is_error("{ 'a: while x { 'b: while y { break 'a } } }"));
+
+    let tree = parse("{ 'a: while x {\n while y { continue 'b } } }");
+    let err = error(&tree).unwrap();
+    assert!(matches!(err.tree_type, TreeType::Error("unknown loop label")));
+    assert_eq!((err.position().line(), err.position().column()), (1, 20));
+    // labels are not visible from functions defined in the loop
+    assert!(is_error("{ 'a: while x { let f = () => { while y { break 'a } } } }"));
+    assert!(is_error("{ 'a: while x { } break 'a }"));
+    assert!(is_error("{ 'a x }"));
+    assert!(is_error("{ 'a: x }"));
+    assert!(is_error("{ ' }"));
+}
+
+#[test]
+fn parse_match() {
+    match parse_no_error("match p { (0, y) if y > 1 => y, [a, ..rest] => a, -1 => 0, _ => x => x }").as_tree_type() {
+        TreeType::Match(_, arms) => {
+            assert_eq!(arms.len(), 4);
+            match arms[0].0 {
+                Pattern::Tuple(ref elems) => {
+                    assert!(matches!(elems[0], Pattern::Lit(ref lit) if lit.tree_type == TreeType::IntLit(0)));
+                    assert_eq!(elems[1], Pattern::Bind("y".to_string()));
+                },
+                ref p => panic!("unexpected pattern: {:?}", p)
+            }
+            assert!(arms[0].1.is_some());
+            assert_eq!(arms[1].0, Pattern::List(vec![Pattern::Bind("a".to_string()), Pattern::Rest(Some("rest".to_string()))]));
+            assert_eq!(arms[3].0, Pattern::Wildcard);
+            assert!(matches!(arms[3].2.tree_type, TreeType::Func(..)));
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    let shown = format!("{}", parse_no_error("match p { (x, [1, .., \"a\"]) if x => -2.5, (x) => x, (x,) => x }"));
+    assert_eq!(shown, "match p {\n(x, [1, .., \"a\", ], ) if x => (-2.5),\nx => x,\n(x, ) => x,\n}");
+    assert_eq!(format!("{}", parse_no_error(&shown)), shown);
+
+    // literals inside patterns are visited like any other tree
+    let mut lits = 0;
+    parse_no_error("match p { [1, (2, x)] => 3 }").for_each(|t| if let TreeType::IntLit(_) = t.tree_type { lits += 1 });
+    assert_eq!(lits, 3);
+
+    let tree = parse("match p {\n (1, ..) => 2 }");
+    let err = error(&tree).unwrap();
+    assert!(matches!(err.tree_type, TreeType::Error("'..' is only allowed in list patterns")));
+    assert_eq!(err.position().line(), 1);
+    assert!(is_error("match p { [.., ..] => 1 }"));
+    assert!(is_error("match p { .. => 1 }"));
+    assert!(is_error("match p { 1 + 2 => 1 }"));
+    assert!(is_error("match p { 1 2 }"));
+    assert!(is_error("match p 1"));
+}
+
+#[test]
+fn parse_destructuring() {
+    match parse_no_error("let (a, [b, ..rest], _) = f()").as_tree_type() {
+        TreeType::DefPattern(Pattern::Tuple(elems), rhs) => {
+            assert_eq!(elems.len(), 3);
+            assert_eq!(elems[1], Pattern::List(vec![Pattern::Bind("b".to_string()), Pattern::Rest(Some("rest".to_string()))]));
+            assert_eq!(elems[2], Pattern::Wildcard);
+            assert!(matches!(rhs.tree_type, TreeType::Call(..)));
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert!(matches!(parse_no_error("let _ = f()").as_tree_type(), TreeType::DefPattern(Pattern::Wildcard, _)));
+    assert!(is_def("let x = 1"));
+
+    match parse_no_error("((a, b), [c, ..rest]) => a").as_tree_type() {
+        TreeType::Func(params, _) => {
+            assert!(matches!(params[0], (Pattern::Tuple(..), None)));
+            assert_eq!(params[1].0, Pattern::List(vec![Pattern::Bind("c".to_string()), Pattern::Rest(Some("rest".to_string()))]));
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    for input in &["let (a, [b, .., ], ) = x", "(((a, _, ), [.., c, ], ) => a)"] {
+        let shown = format!("{}", parse_no_error(input));
+        assert_eq!(&shown, input);
+    }
+
+    let tree = parse("{ let x = 1
+ let (a, ..) = x }");
+    let err = error(&tree).unwrap();
+    assert!(matches!(err.tree_type, TreeType::Error("'..' is only allowed in list patterns")));
+    assert_eq!(err.position().line(), 1);
+    assert!(is_error("let [a, .., ..] = x"));
+    assert!(is_error("let (a, b) x"));
+    assert!(is_error("(a + 1) => a"));
+    assert!(is_error("(a, ..rest, b) => rest"));
+    assert!(is_error("[1, ..rest]"));
+}
+
+#[test]
+fn parse_params() {
+    match parse_no_error("(a, b = 2, ..more) => a").as_tree_type() {
+        TreeType::Func(params, _) => {
+            assert_eq!(params.len(), 3);
+            assert_eq!(params[0], (Pattern::Bind("a".to_string()), None));
+            assert!(matches!(params[1].1, Some(ref d) if d.tree_type == TreeType::IntLit(2)));
+            assert_eq!(params[2], (Pattern::Rest(Some("more".to_string())), None));
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    match parse_no_error("f(x, (1, 2), scale: 3)").as_tree_type() {
+        TreeType::Call(_, args, named) => {
+            assert_eq!(args.len(), 2);
+            assert!(matches!(args[1].tree_type, TreeType::Tuple(..)));
+            assert_eq!(named.len(), 1);
+            assert_eq!(named[0].0, "scale");
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    for input in &["((a, b = (a * 2), .., ) => a)", "f(x, y: 1, z: g(w: 2, ), )"] {
+        let shown = format!("{}", parse_no_error(input));
+        assert_eq!(&shown, input);
+    }
+
+    let tree = parse("f(a: 1,\n a: 2)");
+    let err = error(&tree).unwrap();
+    assert!(matches!(err.tree_type, TreeType::Error("duplicate named argument")));
+    assert_eq!(err.position().line(), 1);
+    assert!(is_error("f(a: 1, 2)"));
+    assert!(is_error("f(1 + 1: 2)"));
+    assert!(is_error("f(a 1)"));
+    assert!(is_error("(a = 1, b) => a"));
+    assert!(is_error("(..a, ..b) => a"));
+}
+
+#[test]
+fn parse_fn() {
+    match parse_no_error("/// halves\nfn half(n, by = 2) { n / by }").as_tree_type() {
+        TreeType::Def(name, rhs, doc, true) => {
+            assert_eq!(name, "half");
+            assert_eq!(doc, Some("halves".to_string()));
+            match rhs.tree_type {
+                TreeType::Func(ref params, _) => assert_eq!(params.len(), 2),
+                ref t => panic!("unexpected tree: {:?}", t)
+            }
+        },
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    assert!(matches!(parse_no_error("let f = () => 1").as_tree_type(), TreeType::Def(_, _, _, false)));
+    // a single parenthesised pattern stays a single parameter
+    match parse_no_error("fn f((a, b)) { a }").as_tree_type() {
+        TreeType::Def(_, rhs, _, _) => assert!(matches!(rhs.tree_type, TreeType::Func(ref params, _) if params.len() == 1)),
+        t => panic!("unexpected tree: {:?}", t)
+    }
+    let shown = format!("{}", parse_no_error("fn f(a, ..rest) { return a }"));
+    assert_eq!(shown, "fn f(a, ..rest, ) {\nreturn a\n}");
+    assert_eq!(format!("{}", parse_no_error(&shown)), shown);
+
+    assert!(is_error("fn (a) { a }"));
+    assert!(is_error("fn f { a }"));
+    assert!(is_error("fn f(a) a"));
+    assert!(is_error("fn f(a + 1) { a }"));
+    assert!(is_error("{ while a { fn f() { break } } }"));
+}
+
 #[test]
 fn parse_if() {
     assert!(is_cond("if 1 2"));
@@ -128,4 +602,599 @@ fn parse_if() {
 #[test]
 fn parse_invalid_empty_tuple() {
     assert!(is_error("(,)"));
-}
\ No newline at end of file
+}
+
+#[test]
+fn eval_closure_capture() {
+    assert_eq!(eval_str("{ let x = 3\n let f = y => x + y\n f(4) }"), Value::Int(7));
+    assert_eq!(eval_str("{ let make = n => (x => x + n)\n let add = make(2)\n add(5) }"), Value::Int(7));
+}
+
+#[test]
+fn eval_closure_assign() {
+    assert_eq!(eval_str("{ let x = 1\n let inc = () => x = x + 1\n inc()\n inc()\n x }"), Value::Int(3));
+}
+
+#[test]
+fn eval_block_scopes() {
+    assert_eq!(eval_str("{ let x = 1\n if true { let x = 2\n x = 3 }\n x }"), Value::Int(1));
+    assert_eq!(eval_str("{ let x = 1\n if true { x = 2 }\n x }"), Value::Int(2));
+    assert_eq!(eval_str("{ let i = 0\n let s = 0\n while i != 3 { let t = i * 2\n s = s + t\n i = i + 1 }\n s }"), Value::Int(6));
+}
+
+#[test]
+fn eval_runtime_errors() {
+    assert_eq!(eval_error("{ x }").error_type, RuntimeErrorType::Undeclared("x".to_string()));
+    assert_eq!(eval_error("{ let x = 1\n let x = 2 }").error_type, RuntimeErrorType::AlreadyDeclared("x".to_string()));
+    assert_eq!(eval_error("{ let f = (a, b) => a\n f(1) }").error_type, RuntimeErrorType::ArityMismatch(2, 1));
+    match eval_error("{ 1 - (2, 3) }").error_type {
+        RuntimeErrorType::TypeMismatch(_) => (),
+        e => panic!("unexpected error: {:?}", e)
+    }
+}
+
+#[test]
+fn eval_runtime_error_position() {
+    let err = eval_error("{\n let x = 1\n x + y\n}");
+    assert_eq!(err.position().line(), 2);
+    assert_eq!(err.position().column(), 5);
+}
+
+#[test]
+fn interpreter_session() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("let x = 2").unwrap();
+    interp.eval_str("let f = y => x * y").unwrap();
+    assert_eq!(interp.eval_str("f(3)").unwrap(), Value::Int(6));
+    assert_eq!(interp.env().get(&"x".to_string()).unwrap(), Value::Int(2));
+}
+
+#[test]
+fn interpreter_errors() {
+    let mut interp = Interpreter::new();
+    match interp.eval_str("(,)") {
+        Err(Error::Parse(errs)) => assert_eq!(errs.len(), 1),
+        r => panic!("unexpected result: {:?}", r)
+    }
+    match interp.eval_str("y") {
+        Err(Error::Runtime(err)) => assert_eq!(err.error_type, RuntimeErrorType::Undeclared("y".to_string())),
+        r => panic!("unexpected result: {:?}", r)
+    }
+}
+
+#[test]
+fn interpreter_native() {
+    let mut interp = Interpreter::new();
+    interp.register("twice", 1, |args| Ok(Value::Int(args[0].clone().to_int()? * 2))).unwrap();
+    assert_eq!(interp.eval_str("twice(4) + 1").unwrap(), Value::Int(9));
+    assert_eq!(interp.eval_str("{ let f = twice\n f(f(1)) }").unwrap(), Value::Int(4));
+    match interp.eval_str("twice(1, 2)") {
+        Err(Error::Runtime(err)) => assert_eq!(err.error_type, RuntimeErrorType::ArityMismatch(1, 2)),
+        r => panic!("unexpected result: {:?}", r)
+    }
+    match interp.eval_str("\n twice(())") {
+        Err(Error::Runtime(err)) => assert_eq!(err.position().line(), 1),
+        r => panic!("unexpected result: {:?}", r)
+    }
+}
+
+#[test]
+fn prelude_builtins() {
+    let mut interp = Interpreter::new();
+    assert_eq!(interp.eval_str("{ let t = (1, 2, 3)\n len(t) }").unwrap(), Value::Int(3));
+    assert_eq!(interp.eval_str("len(\"abc\")").unwrap(), Value::Int(3));
+    assert_eq!(interp.eval_str("str(4) + \"2\"").unwrap(), Value::Str("42".to_string()));
+    assert_eq!(interp.eval_str("num(\"2.5\") * 2").unwrap(), Value::Float(5.0));
+    assert_eq!(interp.eval_str("type_of(num(\"7\"))").unwrap(), Value::Str("int".to_string()));
+    assert_eq!(interp.eval_str("float(7) / 2").unwrap(), Value::Float(3.5));
+    assert_eq!(interp.eval_str("int(\"2.5\")").unwrap(), Value::Int(2));
+    assert_eq!(interp.eval_str("type_of(len)").unwrap(), Value::Str("func".to_string()));
+    assert_eq!(interp.eval_str("assert_eq(1 + 1, 2)").unwrap(), Value::Unit);
+}
+
+#[test]
+fn prelude_assert() {
+    let mut interp = Interpreter::new();
+    match interp.eval_str("\n assert(1 == 2)") {
+        Err(Error::Runtime(err)) => {
+            assert_eq!(err.error_type, RuntimeErrorType::AssertionFailed("false".to_string()));
+            assert_eq!(err.position().line(), 1);
+        },
+        r => panic!("unexpected result: {:?}", r)
+    }
+}
+
+#[test]
+fn eval_list_index() {
+    assert_eq!(eval_str("{ let xs = [1, 2, 3]\n xs[0] + xs[0 - 1] }"), Value::Int(4));
+    assert_eq!(eval_str("{ let xs = [1, [2, 3]]\n xs[1][0] = 5\n xs }"), Value::List(vec![Value::Int(1), Value::List(vec![Value::Int(5), Value::Int(3)])]));
+    assert_eq!(eval_str("{ let t = (1, 2)\n t[1] }"), Value::Int(2));
+    assert_eq!(eval_error("{ let xs = [1]\n xs[1] }").error_type, RuntimeErrorType::IndexOutOfRange(1, 1));
+    assert_eq!(eval_error("{ let xs = [1]\n xs[0 - 2] = 3 }").error_type, RuntimeErrorType::IndexOutOfRange(-2, 1));
+}
+
+#[test]
+fn eval_map() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("let m = #{ a: 1, \"b\": 2, 3: \"c\" }").unwrap();
+    assert_eq!(interp.eval_str("m.a + m[\"b\"]").unwrap(), Value::Int(3));
+    assert_eq!(interp.eval_str("m[3]").unwrap(), Value::Str("c".to_string()));
+    assert_eq!(eval_str("{ let a = 1\n let k = #{ a + 1: \"x\", a: \"y\" }\n (k[2], k.a) }"), eval_str("(\"x\", \"y\")"));
+    interp.eval_str("m.d = #{ e: 4 }").unwrap();
+    interp.eval_str("m.d.e = 5").unwrap();
+    assert_eq!(interp.eval_str("m.d.e").unwrap(), Value::Int(5));
+    interp.eval_str("m = remove(m, \"b\")").unwrap();
+    assert_eq!(interp.eval_str("len(m)").unwrap(), Value::Int(3));
+    assert_eq!(interp.eval_str("keys(m)").unwrap(), Value::List(vec![Value::Str("a".to_string()), Value::Int(3), Value::Str("d".to_string())]));
+    match interp.eval_str("m.b") {
+        Err(Error::Runtime(err)) => assert_eq!(err.error_type, RuntimeErrorType::MissingKey("\"b\"".to_string())),
+        r => panic!("unexpected result: {:?}", r)
+    }
+}
+
+#[test]
+fn eval_numbers() {
+    assert_eq!(eval_str("7 / 2"), Value::Int(3));
+    assert_eq!(eval_str("(0 - 7) / 2"), Value::Int(-3));
+    assert_eq!(eval_str("9007199254740993 + 0"), Value::Int(9007199254740993));
+    assert_eq!(eval_str("1 + 0.5"), Value::Float(1.5));
+    assert_eq!(eval_str("1.0 == 1"), Value::Bool(true));
+    assert_eq!(eval_str("1 == 1"), Value::Bool(true));
+    assert_eq!(eval_str("true != false"), Value::Bool(true));
+    assert_eq!(eval_error("9223372036854775807 + 1").error_type, RuntimeErrorType::IntegerOverflow);
+    assert_eq!(eval_error("1 / 0").error_type, RuntimeErrorType::DivisionByZero);
+    assert!(matches!(eval_error("if 1 { 2 }").error_type, RuntimeErrorType::TypeMismatch(_)));
+    assert!(matches!(eval_error("true + 1").error_type, RuntimeErrorType::TypeMismatch(_)));
+}
+
+#[test]
+fn eval_interpolation() {
+    let mut interp = Interpreter::new();
+    interp.eval_str(r#"let name = "bob""#).unwrap();
+    interp.eval_str("let age = 41").unwrap();
+    assert_eq!(interp.eval_str(r#""hello {name}, you are {age + 1}""#).unwrap(), Value::Str("hello bob, you are 42".to_string()));
+    assert_eq!(interp.eval_str(r#""[{age:5}|{name:<5}|{name:>5}]""#).unwrap(), Value::Str("[   41|bob  |  bob]".to_string()));
+    assert_eq!(interp.eval_str(r#""{3.14159:.2} {(0 - 7):04} {name:.1}""#).unwrap(), Value::Str("3.14 -007 b".to_string()));
+    assert_eq!(interp.eval_str(r#""{#{ a: len("\{\}") }.a}""#).unwrap(), Value::Str("2".to_string()));
+    // only floats and strings take a precision, widths are bounded
+    match interp.eval_str(r#""{1:08.3}""#) {
+        Err(Error::Runtime(err)) => assert!(matches!(err.error_type, RuntimeErrorType::TypeMismatch(_))),
+        r => panic!("unexpected result: {:?}", r)
+    }
+    assert_eq!(interp.eval_str(r#""{1:1024}""#).unwrap().to_str().len(), 1024);
+    assert!(interp.parse_str(r#""{1:1025}""#).is_err());
+    assert!(interp.parse_str(r#""{1.5:.100000}""#).is_err());
+    match interp.eval_str("\"x = {\n undeclared}\"") {
+        Err(Error::Runtime(err)) => {
+            assert_eq!(err.position().line(), 1);
+            assert_eq!(err.position().column(), 1);
+        },
+        r => panic!("unexpected result: {:?}", r)
+    }
+}
+
+#[test]
+fn eval_comparisons() {
+    assert_eq!(eval_str("1 < 2"), Value::Bool(true));
+    assert_eq!(eval_str("2 <= 2"), Value::Bool(true));
+    assert_eq!(eval_str("1.5 > 2"), Value::Bool(false));
+    assert_eq!(eval_str("3 >= 2.5"), Value::Bool(true));
+    // mixed comparisons are exact, even where floats can't represent every int
+    assert_eq!(eval_str("2 == 2.0"), Value::Bool(true));
+    assert_eq!(eval_str("9007199254740993 == 9007199254740992.0"), Value::Bool(false));
+    assert_eq!(eval_str("9007199254740993 > 9007199254740992.0"), Value::Bool(true));
+    assert_eq!(eval_str("9007199254740992.0 < 9007199254740993"), Value::Bool(true));
+    assert_eq!(eval_str("9223372036854775807 < 9223372036854775807.0"), Value::Bool(true));
+    assert_eq!(eval_str("(0 - 2) < (0 - 1.5)"), Value::Bool(true));
+    assert_eq!(eval_str("(0 - 1) > (0 - 1.5)"), Value::Bool(true));
+    assert_eq!(eval_str(r#""abc" < "abd""#), Value::Bool(true));
+    assert_eq!(eval_str(r#""b" > "abc""#), Value::Bool(true));
+    assert_eq!(eval_str("7 % 3"), Value::Int(1));
+    assert_eq!(eval_str("(0 - 7) % 3"), Value::Int(-1));
+    assert_eq!(eval_str("7.5 % 2"), Value::Float(1.5));
+    assert_eq!(eval_error("1 % 0").error_type, RuntimeErrorType::DivisionByZero);
+    assert!(matches!(eval_error(r#"1 < "a""#).error_type, RuntimeErrorType::TypeMismatch(_)));
+    assert_eq!(eval_str("{ let i = 0 let n = 0 while i < 5 { n = n + i i = i + 1 } n }"), Value::Int(10));
+}
+
+#[test]
+fn eval_logical() {
+    assert_eq!(eval_str("true && false"), Value::Bool(false));
+    assert_eq!(eval_str("false || true"), Value::Bool(true));
+    assert_eq!(eval_str("1 < 2 && 2 < 3"), Value::Bool(true));
+    // the rhs is never evaluated
+    assert_eq!(eval_str("false && undeclared"), Value::Bool(false));
+    assert_eq!(eval_str("true || 1 / 0"), Value::Bool(true));
+    assert!(matches!(eval_error("true && 1").error_type, RuntimeErrorType::TypeMismatch(_)));
+}
+
+#[test]
+fn eval_unary() {
+    assert_eq!(eval_str("-1"), Value::Int(-1));
+    assert_eq!(eval_str("-2 * 3"), Value::Int(-6));
+    assert_eq!(eval_str("1 - -2"), Value::Int(3));
+    assert_eq!(eval_str("--4.5"), Value::Float(4.5));
+    assert_eq!(eval_str("+3"), Value::Int(3));
+    assert_eq!(eval_str("!true"), Value::Bool(false));
+    assert_eq!(eval_str("!(1 < 2) || true"), Value::Bool(true));
+    assert_eq!(eval_str("{ let l = [1, 2] let m = -l[1] m }"), Value::Int(-2));
+    assert_eq!(eval_str("{ let x = 5\n -x }"), Value::Int(-5));
+    assert_eq!(eval_str("{ let x = 1\n-1\n x }"), Value::Int(1));
+    assert_eq!(eval_error("-(0 - 9223372036854775807 - 1)").error_type, RuntimeErrorType::IntegerOverflow);
+    assert!(matches!(eval_error(r#"-"a""#).error_type, RuntimeErrorType::TypeMismatch(_)));
+    assert!(matches!(eval_error(r#"+"a""#).error_type, RuntimeErrorType::TypeMismatch(_)));
+    assert!(matches!(eval_error("!1").error_type, RuntimeErrorType::TypeMismatch(_)));
+}
+
+#[test]
+fn eval_compound_assign() {
+    assert_eq!(eval_str("{ let i = 0 let n = 0 while i < 5 { i += 1 n += i } n }"), Value::Int(15));
+    assert_eq!(eval_str("{ let x = 10 x -= 4 x *= 3 x /= 2 x }"), Value::Int(9));
+    assert_eq!(eval_str(r#"{ let s = "a" s += 1 s }"#), Value::Str("a1".to_string()));
+    assert_eq!(eval_str("{ let m = #{ a: [1, 2] } m.a[1] += 5 m }"), eval_str("#{ a: [1, 7] }"));
+
+    // the index is evaluated once
+    let mut interp = Interpreter::new();
+    interp.eval_str("let calls = 0").unwrap();
+    interp.eval_str("let next = () => { calls += 1 0 }").unwrap();
+    interp.eval_str("let l = [10]").unwrap();
+    assert_eq!(interp.eval_str("l[next()] += 1").unwrap(), Value::Int(11));
+    assert_eq!(interp.eval_str("calls").unwrap(), Value::Int(1));
+
+    assert!(matches!(eval_error("{ let x = 1 x += true }").error_type, RuntimeErrorType::TypeMismatch(_)));
+    assert_eq!(eval_error("y += 1").error_type, RuntimeErrorType::Undeclared("y".to_string()));
+    assert_eq!(eval_error("{ let x = 1 x /= 0 }").error_type, RuntimeErrorType::DivisionByZero);
+}
+
+#[test]
+fn eval_precedence() {
+    assert_eq!(eval_str("10 - 2 - 3"), Value::Int(5));
+    assert_eq!(eval_str("100 / 10 / 5"), Value::Int(2));
+    assert_eq!(eval_str("2 * 3 + 4 * 5 - 6 / 2"), Value::Int(23));
+    assert_eq!(eval_str("1 + 2 * 3 - 4 * 5 + 6"), Value::Int(-7));
+    assert_eq!(eval_str("3 == 1 + 2 * 3 - 4"), Value::Bool(true));
+    assert_eq!(eval_str("17 % 5 * 2"), Value::Int(4));
+    assert_eq!(eval_str("-2 * -3 - -1"), Value::Int(7));
+    assert_eq!(eval_str("{ let add = x => y => x + y add(1)(2) * 3 }"), Value::Int(9));
+}
+
+#[test]
+fn eval_for() {
+    let mut interp = Interpreter::new();
+    assert_eq!(eval_str("{ let sum = 0 for x in [1, 2, 3] { sum += x } sum }"), Value::Int(6));
+    assert_eq!(eval_str("{ let sum = 0 for i in 0..5 { sum += i } sum }"), Value::Int(10));
+    assert_eq!(eval_str("{ let sum = 0 for i in 1..=5 { sum += i } sum }"), Value::Int(15));
+    assert_eq!(eval_str("{ let sum = 0 for i in 5..1 { sum += i } sum }"), Value::Int(0));
+    assert_eq!(eval_str(r#"{ let s = "" for c in "abc" { s = c + s } s }"#), Value::Str("cba".to_string()));
+    assert_eq!(eval_str(r#"{ let s = "" for (k, v) in #{ a: 1, b: 2 } { s += "{k}{v}" } s }"#), Value::Str("a1b2".to_string()));
+    assert_eq!(eval_str("{ let sum = 0 for (a, (b, c)) in [(1, (2, 3)), (4, [5, 6])] { sum += a * b + c } sum }"), Value::Int(31));
+    // ranges are never materialised
+    assert_eq!(interp.eval_str("len(0..9223372036854775807)").unwrap(), Value::Int(9223372036854775807));
+    assert_eq!(interp.eval_str("len(3..=1)").unwrap(), Value::Int(0));
+    assert_eq!(interp.eval_str("len(3..=3)").unwrap(), Value::Int(1));
+    assert_eq!(interp.eval_str("(1..=2) == (1..3)").unwrap(), Value::Bool(true));
+    // the length of a range is only computed when asked for
+    assert_eq!(eval_str("{ let n = 0\n for i in 0..=9223372036854775807 { if i == 3 { break } n += 1 }\n n }"), Value::Int(3));
+    assert_eq!(eval_str("{ let s = 0\n for i in 9223372036854775806..=9223372036854775807 { s += 1 }\n s }"), Value::Int(2));
+    match interp.eval_str("len(0..=9223372036854775807)") {
+        Err(Error::Runtime(err)) => assert_eq!(err.error_type, RuntimeErrorType::IntegerOverflow),
+        r => panic!("unexpected result: {:?}", r)
+    }
+    assert_eq!(eval_str("{ let s = \"\"\n for c in \"héllo\" { if c == \"l\" { break } s += c }\n s }"), Value::Str("hé".to_string()));
+    match interp.eval_str("len((0 - 9223372036854775807 - 1)..9223372036854775807)") {
+        Err(Error::Runtime(err)) => assert_eq!(err.error_type, RuntimeErrorType::IntegerOverflow),
+        r => panic!("unexpected result: {:?}", r)
+    }
+    assert_eq!(interp.eval_str("type_of(1..=2)").unwrap(), Value::Str("range".to_string()));
+
+    // the loop variable is scoped to the body
+    match interp.eval_str("{ for x in [1] { } x }") {
+        Err(Error::Runtime(err)) => assert_eq!(err.error_type, RuntimeErrorType::Undeclared("x".to_string())),
+        r => panic!("unexpected result: {:?}", r)
+    }
+    assert!(matches!(eval_error("{ for x in 1 { } }").error_type, RuntimeErrorType::TypeMismatch(_)));
+    assert_eq!(eval_error("{ for (a, b) in [(1, 2, 3)] { } }").error_type, RuntimeErrorType::ArityMismatch(2, 3));
+}
+
+#[test]
+fn eval_jumps() {
+    assert_eq!(eval_str("{ let i = 0 while true { i += 1 if i == 5 { break } else { } } i }"), Value::Int(5));
+    assert_eq!(eval_str("{ let i = 0 while true { i += 1 if i == 5 { break i * 10 } else { } } }"), Value::Int(50));
+    assert_eq!(eval_str("{ let sum = 0 for i in 0..10 { if i % 2 == 0 { continue } else { } sum += i } sum }"), Value::Int(25));
+    assert_eq!(eval_str("{ let i = 0 let sum = 0 while i < 10 { i += 1 if i % 2 == 0 { continue } else { } sum += i } sum }"), Value::Int(25));
+    assert_eq!(eval_str("{ for x in [1, 5, 7] { if x > 3 { break x } else { } } }"), Value::Int(5));
+    assert_eq!(eval_str("{ for x in 0..3 { } }"), Value::Unit);
+
+    // inner loops are left without leaving the outer ones
+    assert_eq!(eval_str("{ let n = 0 for i in 0..3 { for j in 0..3 { if j == 1 { break } else { } n += 1 } } n }"), Value::Int(3));
+
+    let mut interp = Interpreter::new();
+    interp.eval_str("let find = (l, v) => { for (i, x) in l { if x == v { return i } else { } } -1 }").unwrap();
+    assert_eq!(interp.eval_str("find([(0, 4), (1, 8)], 8)").unwrap(), Value::Int(1));
+    assert_eq!(interp.eval_str("find([(0, 4)], 3)").unwrap(), Value::Int(-1));
+    interp.eval_str("let sign = x => { if x < 0 { return -1 } else { } if x == 0 { return } else { } 1 }").unwrap();
+    assert_eq!(interp.eval_str("sign(-3)").unwrap(), Value::Int(-1));
+    assert_eq!(interp.eval_str("sign(0)").unwrap(), Value::Unit);
+    assert_eq!(interp.eval_str("sign(3)").unwrap(), Value::Int(1));
+    // a return only leaves the innermost function
+    interp.eval_str("let outer = () => { let inner = () => { return 1 } inner() + 1 }").unwrap();
+    assert_eq!(interp.eval_str("outer()").unwrap(), Value::Int(2));
+}
+
+#[test]
+fn eval_labels() {
+    let search = "{ let found = () 'rows: for i in 0..10 { for j in 0..10 { if i * j == 42 { found = (i, j) break 'rows } else { } } } found }";
+    assert_eq!(eval_str(search), Value::Tuple(vec![Value::Int(6), Value::Int(7)]));
+    assert_eq!(eval_str("{ 'outer: while true { while true { break 'outer 3 } } }"), Value::Int(3));
+    // continue skips the rest of the outer iteration
+    let skip = "{ let n = 0 'outer: for i in 0..4 { for j in 0..4 { if j > i { continue 'outer } else { } n += 1 } } n }";
+    assert_eq!(eval_str(skip), Value::Int(10));
+    // an unlabeled break leaves the innermost loop
+    assert_eq!(eval_str("{ let n = 0 'outer: for i in 0..3 { for j in 0..3 { break } n += 1 } n }"), Value::Int(3));
+    assert_eq!(eval_str("{ let n = 0 'a: for i in 0..3 { 'b: for j in 0..3 { break 'b } n += 1 } n }"), Value::Int(3));
+}
+
+#[test]
+fn eval_match() {
+    let mut interp = Interpreter::new();
+    interp.eval_str(r#"let describe = v => match v {
+        0 => "zero",
+        -1 => "minus one",
+        "hi" => "greeting",
+        true => "yes",
+        (0, y) => "on the y axis at {y}",
+        (x, y) if x == y => "diagonal",
+        (x, _) => "at x {x}",
+        [] => "empty",
+        [single] => "just {single}",
+        [first, .., last] => "from {first} to {last}",
+        _ => "something else"
+    }"#).unwrap();
+    let mut describe = |input: &str| interp.eval_str(&format!("describe({})", input)).unwrap();
+    assert_eq!(describe("0"), Value::Str("zero".to_string()));
+    assert_eq!(describe("0.0"), Value::Str("zero".to_string()));
+    assert_eq!(describe("-1"), Value::Str("minus one".to_string()));
+    assert_eq!(describe(r#""hi""#), Value::Str("greeting".to_string()));
+    assert_eq!(describe("true"), Value::Str("yes".to_string()));
+    assert_eq!(describe("(0, 5)"), Value::Str("on the y axis at 5".to_string()));
+    assert_eq!(describe("(2, 2)"), Value::Str("diagonal".to_string()));
+    assert_eq!(describe("(2, 3)"), Value::Str("at x 2".to_string()));
+    assert_eq!(describe("[]"), Value::Str("empty".to_string()));
+    assert_eq!(describe("[7]"), Value::Str("just 7".to_string()));
+    assert_eq!(describe("[1, 2, 3]"), Value::Str("from 1 to 3".to_string()));
+    assert_eq!(describe("(1, 2, 3)"), Value::Str("something else".to_string()));
+    assert_eq!(describe("#{}"), Value::Str("something else".to_string()));
+
+    assert_eq!(eval_str("match [1, 2, 3, 4] { [a, ..rest] => rest }"), eval_str("[2, 3, 4]"));
+    assert_eq!(eval_str("match [1, 2, 3, 4] { [.., y, z] => y * z }"), Value::Int(12));
+    assert_eq!(eval_str("match [1] { [a, b, ..rest] => 0, [a, ..rest] => rest }"), Value::List(vec![]));
+    // tuples as return values
+    assert_eq!(eval_str("{ let divmod = (a, b) => (a / b, a % b) match divmod(17, 5) { (q, r) => q * 10 + r } }"), Value::Int(32));
+    // bindings don't leak out of their arm
+    assert_eq!(eval_str("{ let x = 1 match 5 { x if x > 10 => x, _ => x } }"), Value::Int(1));
+
+    let err = eval_error("{ let v = 3\n match v { 1 => 1, (a, b) => 2 } }");
+    assert_eq!(err.error_type, RuntimeErrorType::NoMatch("3".to_string()));
+    assert_eq!(err.position().line(), 1);
+    assert!(matches!(eval_error("match 1 { x if x => 1 }").error_type, RuntimeErrorType::TypeMismatch(_)));
+    assert_eq!(eval_error("match (1, 2) { (x, x) => x }").error_type, RuntimeErrorType::AlreadyDeclared("x".to_string()));
+}
+
+#[test]
+fn eval_destructuring() {
+    assert_eq!(eval_str("{ let divmod = (a, b) => (a / b, a % b)\n let (q, r) = divmod(17, 5)\n q * 10 + r }"), Value::Int(32));
+    assert_eq!(eval_str("{ let (a, (b, c)) = (1, (2, 3))\n a + b * c }"), Value::Int(7));
+    assert_eq!(eval_str("{ let [first, ..rest, last] = [1, 2, 3, 4]\n (first, rest, last) }"), eval_str("(1, [2, 3], 4)"));
+    assert_eq!(eval_str("{ let [a, ..] = [1]\n a }"), Value::Int(1));
+    assert_eq!(eval_str("{ let (_, b) = [1, 2]\n b }"), Value::Int(2));
+    assert_eq!(eval_str("{ let _ = 1\n 2 }"), Value::Int(2));
+    // parameters take the same patterns
+    assert_eq!(eval_str("{ let f = ((a, b), [c, ..rest]) => a + b + c + rest[1]\n f((1, 2), [3, 4, 5]) }"), Value::Int(11));
+    assert_eq!(eval_str("{ let s = 0\n for (i, [x, ..]) in [(1, [2]), (3, [4, 5])] { s += i * x }\n s }"), Value::Int(14));
+    assert_eq!(eval_str("{ let f = (x, -1) => x\n f(2, -1) }"), Value::Int(2));
+    assert_eq!(eval_str("{ let (a, -1.5) = (1, -1.5)\n a }"), Value::Int(1));
+    assert_eq!(eval_error("{ let f = (x, -1) => x\n f(2, 1) }").error_type, RuntimeErrorType::NoMatch("1".to_string()));
+
+    let err = eval_error("{ let t = (1, 2, 3)\n let (a, b) = t }");
+    assert_eq!(err.error_type, RuntimeErrorType::ArityMismatch(2, 3));
+    assert_eq!(err.position().line(), 1);
+    assert_eq!(eval_error("let [a, b, ..rest] = [1]").error_type, RuntimeErrorType::ArityMismatch(2, 1));
+    assert!(matches!(eval_error("let (a, b) = 1").error_type, RuntimeErrorType::TypeMismatch(_)));
+    assert_eq!(eval_error("let (a, a) = (1, 2)").error_type, RuntimeErrorType::AlreadyDeclared("a".to_string()));
+    let err = eval_error("{ let f = (a, (b, c)) => a\n f(1,\n 2) }");
+    assert!(matches!(err.error_type, RuntimeErrorType::TypeMismatch(_)));
+    assert_eq!(err.position().line(), 2);
+}
+
+#[test]
+fn eval_params() {
+    let mut interp = Interpreter::new();
+    interp.eval_str("let scaled = (x, scale = 2, offset = scale * 10) => x * scale + offset").unwrap();
+    assert_eq!(interp.eval_str("scaled(1)").unwrap(), Value::Int(22));
+    assert_eq!(interp.eval_str("scaled(1, 3)").unwrap(), Value::Int(33));
+    assert_eq!(interp.eval_str("scaled(1, offset: 0)").unwrap(), Value::Int(2));
+    assert_eq!(interp.eval_str("scaled(offset: 1, x: 5, scale: 3)").unwrap(), Value::Int(16));
+
+    interp.eval_str("let count = (first, ..others) => (first, len(others))").unwrap();
+    assert_eq!(interp.eval_str("count(1)").unwrap(), eval_str("(1, 0)"));
+    assert_eq!(interp.eval_str("count(1, 2, 3)").unwrap(), eval_str("(1, 2)"));
+    assert_eq!(eval_str("{ let f = (..) => 1\n f(1, 2) }"), Value::Int(1));
+    assert_eq!(eval_str("{ let f = (a, ..rest) => rest\n f(a: 1) }"), Value::List(vec![]));
+
+    match interp.eval_str("scaled(1,\n factor: 2)") {
+        Err(Error::Runtime(err)) => {
+            assert_eq!(err.error_type, RuntimeErrorType::UnknownArgument("factor".to_string()));
+            assert_eq!(err.position().line(), 1);
+        },
+        r => panic!("unexpected result: {:?}", r)
+    }
+    match interp.eval_str("scaled(1, x: 2)") {
+        Err(Error::Runtime(err)) => assert_eq!(err.error_type, RuntimeErrorType::DuplicateArgument("x".to_string())),
+        r => panic!("unexpected result: {:?}", r)
+    }
+    match interp.eval_str("scaled(scale: 1)") {
+        Err(Error::Runtime(err)) => assert_eq!(err.error_type, RuntimeErrorType::ArityMismatch(1, 1)),
+        r => panic!("unexpected result: {:?}", r)
+    }
+    assert_eq!(eval_error("{ let f = (a, b = 1) => a\n f(1, 2, 3) }").error_type, RuntimeErrorType::ArityMismatch(2, 3));
+    assert_eq!(eval_error("{ let f = (a) => a\n f(1, b: 2) }").error_type, RuntimeErrorType::UnknownArgument("b".to_string()));
+    match interp.eval_str("len(x: [1])") {
+        Err(Error::Runtime(err)) => assert_eq!(err.error_type, RuntimeErrorType::UnknownArgument("x".to_string())),
+        r => panic!("unexpected result: {:?}", r)
+    }
+}
+
+
+#[test]
+fn eval_fn() {
+    assert_eq!(eval_str("{ fn fact(n) { if n < 2 { 1 } else { n * fact(n - 1) } }\n fact(5) }"), Value::Int(120));
+    // declarations are hoisted, so functions can be used before them and call each other
+    let mut interp = Interpreter::new();
+    let r = interp.eval_str("{
+        let r = (is_even(4), is_odd(3), is_even(3))
+        fn is_even(n) { if n == 0 { true } else { is_odd(n - 1) } }
+        fn is_odd(n) { if n == 0 { false } else { is_even(n - 1) } }
+        r
+    }").unwrap();
+    assert_eq!(r, eval_str("(true, true, false)"));
+    assert_eq!(interp.eval_str("{ let t = twice(4)\n fn twice(x) { x * 2 }\n t }").unwrap(), Value::Int(8));
+    assert_eq!(interp.eval_str("{ fn sum(l) { match l { [] => 0, [x, ..rest] => x + sum(rest) } }\n sum([1, 2, 3]) }").unwrap(), Value::Int(6));
+    assert_eq!(eval_str("{ fn first(l) { for x in l { return x } -1 }\n first([]) }"), Value::Int(-1));
+    assert!(matches!(eval_str("{ fn f() { 1 } }"), Value::Func(..)));
+    // top level declarations are hoisted too
+    assert_eq!(interp.eval_str("{ let a = late()\n fn late() { 5 }\n a }").unwrap(), Value::Int(5));
+
+    // a let isn't hoisted, an inner declaration still shadows outer names
+    assert_eq!(eval_str("{ let f = () => 1\n { let g = f\n let f = () => g() + 1\n f() } }"), Value::Int(2));
+    assert_eq!(eval_error("{ let x = g()\n let g = () => 1 }").error_type, RuntimeErrorType::Undeclared("g".to_string()));
+    let err = eval_error("{ let f = 1\n fn f() { 2 } }");
+    assert_eq!(err.error_type, RuntimeErrorType::AlreadyDeclared("f".to_string()));
+}
+
+#[test]
+fn eval_tail_calls() {
+    // calls in tail position reuse the caller's frame, so they neither grow the native stack nor count towards the limit
+    assert_eq!(eval_str("{ fn count(n, acc) { if n == 0 { acc } else { count(n - 1, acc + 1) } }\n count(100000, 0) }"), Value::Int(100000));
+    assert_eq!(eval_str("{ fn sum(l, acc) { match l { [] => acc, [x, ..rest] => sum(rest, acc + x) } }\n sum([1, 2, 3, 4], 0) }"), Value::Int(10));
+    assert_eq!(eval_str("{
+        fn is_even(n) { if n == 0 { true } else { is_odd(n - 1) } }
+        fn is_odd(n) { if n == 0 { false } else { { is_even(n - 1) } } }
+        is_even(10001)
+    }"), Value::Bool(false));
+    // returning from a tail called function returns from that function only
+    assert_eq!(eval_str("{ fn g(x) { if x > 0 { return x * 2 }\n 0 }\n fn f(x) { g(x) }\n f(3) + 1 }"), Value::Int(7));
+}
+
+#[test]
+fn eval_call_depth() {
+    let mut interp = Interpreter::new();
+    interp.env_mut().set_max_call_depth(20);
+    assert_eq!(interp.env().max_call_depth(), 20);
+    interp.eval_str("fn down(n) { if n == 0 { 0 } else { 1 + down(n - 1) } }").unwrap();
+    assert_eq!(interp.eval_str("down(19)").unwrap(), Value::Int(19));
+    let err = match interp.eval_str("down(100)") {
+        Err(Error::Runtime(err)) => err,
+        r => panic!("unexpected result: {:?}", r)
+    };
+    match err.error_type {
+        RuntimeErrorType::CallDepthExceeded(ref trace) => {
+            assert_eq!(trace.len(), 21);
+            assert!(trace.iter().all(|(name, _)| name == "down"));
+            assert_eq!(trace[0].1.line, 0);
+            assert_eq!(trace[1].1.line, 0);
+        },
+        ref e => panic!("unexpected error: {:?}", e)
+    }
+    let msg = err.to_string();
+    assert!(msg.contains("maximum call depth of 20 exceeded"));
+    assert!(msg.contains("... 11 more calls"));
+    // the depth is back to zero after an error
+    assert_eq!(interp.eval_str("down(19)").unwrap(), Value::Int(19));
+    // tail calls don't count
+    assert_eq!(interp.eval_str("{ fn loop(n) { if n == 0 { 0 } else { loop(n - 1) } }\n loop(1000) }").unwrap(), Value::Int(0));
+}
+
+#[test]
+fn eval_frees_frames() {
+    let probe = Rc::new(NativeFunc::new("probe", 0, |_| Ok(Value::Unit)));
+    let mut env = Env::new();
+    env.def(&"probe".to_string(), Value::Native(probe.clone())).unwrap();
+    let count = Rc::strong_count(&probe);
+    // functions declared in a call keep its frame alive only as long as they are reachable
+    for input in &["{ fn f(x) { fn inner() { x }\n 0 }\n f(probe) }",
+                   "{ let g = (x) => { let inner = () => x\n inner() }\n g(probe) }",
+                   "{ let h = (x) => { fn a() { b() }\n fn b() { x }\n 0 }\n h(probe) }"] {
+        eval(&parse_no_error(input), &mut env).unwrap();
+        assert_eq!(Rc::strong_count(&probe), count, "{}", input);
+    }
+    let kept = eval(&parse_no_error("{ let k = (x) => { fn inner() { x }\n inner }\n k(probe) }"), &mut env).unwrap();
+    assert_eq!(Rc::strong_count(&probe), count + 1);
+    drop(kept);
+    assert_eq!(Rc::strong_count(&probe), count);
+    drop(env);
+    assert_eq!(Rc::strong_count(&probe), 1);
+}
+
+#[test]
+fn eval_stack_limit() {
+    // evaluations get a stack sized for the call depth, whatever the stack of the calling thread
+    let thread = ::std::thread::Builder::new().stack_size(2 << 20).spawn(|| {
+        let mut interp = Interpreter::new();
+        interp.eval_str("fn sum(n) { if n == 0 { 0 } else { n + sum(n - 1) } }").unwrap();
+        assert_eq!(interp.eval_str("sum(900)").unwrap(), Value::Int(405450));
+        interp.eval_str("fn sum_list(l) { match l { [] => 0, [x, ..rest] => x + sum_list(rest) } }").unwrap();
+        let list = (0..300).map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+        assert_eq!(interp.eval_str(&format!("sum_list([{}])", list)).unwrap(), Value::Int(44850));
+        match interp.eval_str("sum(5000)") {
+            Err(Error::Runtime(err)) => match err.error_type {
+                RuntimeErrorType::CallDepthExceeded(ref trace) => assert_eq!(trace.len(), DEFAULT_MAX_CALL_DEPTH + 1),
+                ref e => panic!("unexpected error: {:?}", e)
+            },
+            r => panic!("unexpected result: {:?}", r)
+        }
+
+        // calls not in tail position, with nested trees in between
+        interp.eval_str("fn down(n) { for i in 0..1 { while true { match n { 0 => return 0, _ => { let r = [(1 + { { down(n - 1) } })] return r[0] } } } } }").unwrap();
+        assert_eq!(interp.eval_str("down(300)").unwrap(), Value::Int(300));
+        match interp.eval_str("down(100000)") {
+            Err(Error::Runtime(err)) => assert!(matches!(err.error_type, RuntimeErrorType::CallDepthExceeded(_) | RuntimeErrorType::StackExhausted(_))),
+            r => panic!("unexpected result: {:?}", r)
+        }
+        // the next evaluation starts from an empty stack again
+        assert_eq!(interp.eval_str("down(10)").unwrap(), Value::Int(10));
+    }).unwrap();
+    thread.join().unwrap();
+}
+
+#[test]
+fn eval_nesting_limit() {
+    // the parser needs a big stack for this input, evaluation gets its own anyway
+    let thread = ::std::thread::Builder::new().stack_size(32 << 20).spawn(|| {
+        // trees nested deeper than the stack allowed for the call depth fail cleanly
+        let mut interp = Interpreter::new();
+        interp.env_mut().set_max_call_depth(1);
+        let nested = format!("{}1{}", "if true { ".repeat(1000), " }".repeat(1000));
+        let err = match interp.eval_str(&nested) {
+            Err(Error::Runtime(err)) => err,
+            r => panic!("unexpected result: {:?}", r)
+        };
+        assert_eq!(err.error_type, RuntimeErrorType::StackExhausted(Vec::new()));
+        assert!(err.to_string().contains("evaluation nested too deeply"));
+        interp.env_mut().set_max_call_depth(DEFAULT_MAX_CALL_DEPTH);
+        assert_eq!(interp.eval_str(&nested).unwrap(), Value::Int(1));
+    }).unwrap();
+    thread.join().unwrap();
+}
+
+#[test]
+fn eval_native_panic() {
+    let mut interp = Interpreter::new();
+    interp.register("boom", 1, |args| if args[0] == Value::Bool(true) { panic!("boom") } else { Ok(Value::Int(0)) }).unwrap();
+    interp.eval_str("fn f(n, fail) { if n == 0 { boom(fail) } else { 1 + f(n - 1, fail) } }").unwrap();
+    interp.env_mut().set_max_call_depth(12);
+    // the panic goes on in the calling thread
+    let panicked = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| interp.eval_str("f(10, true)")));
+    assert!(panicked.is_err());
+    // the calls running when it happened don't count anymore
+    assert_eq!(interp.eval_str("f(10, false)").unwrap(), Value::Int(10));
+}
@@ -1,5 +1,6 @@
 
 use position::*;
+use format::*;
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum TokenType {
@@ -7,7 +8,9 @@ pub enum TokenType {
     Ident(String),
 
     StrLit(String),
-    NumLit(String),
+    FmtStr(Vec<FmtPart>),
+    IntLit(i64),
+    FloatLit(f64),
 
     LeftPar,
     RightPar,
@@ -18,29 +21,60 @@ pub enum TokenType {
     LeftBrace,
     RightBrace,
 
+    HashLeftBrace,
+
     Assign,
+    PlusAssign,
+    MinusAssign,
+    StarAssign,
+    SlashAssign,
 
     Plus,
     Minus,
     Star,
     Slash,
+    Percent,
 
     Eq,
     Neq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+
+    And,
+    Or,
     Not,
 
     Comma,
     Colon,
+    Dot,
+    DotDot,
+    DotDotEq,
+
+    DocComment(String),
 
     FatArrow,
 
     If,
     Else,
+    Match,
     While,
+    For,
+    In,
+    Break,
+    Continue,
+    Return,
+    // without the leading quote
+    Label(String),
+
+    True,
+    False,
 
     Let,
+    Fn,
 
-    Error
+    Error(&'static str)
 }
 
 impl TokenType {
@@ -52,7 +86,14 @@ impl TokenType {
     }
 }
 
-#[derive(Clone, Debug)]
+// a piece of an interpolated string, expressions are tokenized but not parsed
+#[derive(Clone, PartialEq, Debug)]
+pub enum FmtPart {
+    Lit(String),
+    Expr(Vec<Token>, Option<FmtSpec>)
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct Token {
     pub token: TokenType,
     pub pos: Position
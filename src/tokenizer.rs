@@ -1,5 +1,6 @@
 use position::*;
 use token::*;
+use format::*;
 
 use std::str::Chars;
 
@@ -17,33 +18,329 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
-    fn next_str(&mut self) -> TokenType {
+    // the opening '"' has already been read, '"""' starts a string that ends with '"""'
+    // strings containing '{...}' are interpolated
+    fn next_str(&mut self, pos: Position) -> Token {
+        let triple = self.chars.as_str().starts_with("\"\"");
+        if triple {
+            self.next_char();
+            self.next_char();
+            // a line break right after the opening quotes is not part of the string
+            if self.peek_char() == Some('\n') {
+                self.next_char();
+            }
+        }
+
         let mut str_lit = String::new();
+        let mut parts = Vec::new();
+        // keep going after a bad escape so the rest of the string isn't parsed as code
+        let mut error = None;
         loop {
-            if let Some(c) = self.next_char() {
-                if c == '"' {
-                    return TokenType::StrLit(str_lit);
+            let char_pos = self.pos.clone();
+            match self.next_char() {
+                Some('"') if !triple => break,
+                Some('"') if self.chars.as_str().starts_with("\"\"") => {
+                    self.next_char();
+                    self.next_char();
+                    break;
+                },
+                Some('\\') => match self.next_escape() {
+                    Ok(c) => str_lit.push(c),
+                    Err(err) => if error.is_none() {
+                        error = Some(TokenType::Error(err).with_pos(char_pos));
+                    }
+                },
+                Some('{') => match self.next_interpolation(char_pos) {
+                    Ok(part) => {
+                        if !str_lit.is_empty() {
+                            parts.push(FmtPart::Lit(str_lit));
+                            str_lit = String::new();
+                        }
+                        parts.push(part);
+                    },
+                    Err(err) => if error.is_none() {
+                        error = Some(err);
+                    }
+                },
+                Some(c) => str_lit.push(c),
+                None => return TokenType::Error("unterminated string").with_pos(pos)
+            }
+        }
+
+        if let Some(err) = error {
+            err
+        } else if parts.is_empty() {
+            TokenType::StrLit(str_lit).with_pos(pos)
+        } else {
+            if !str_lit.is_empty() {
+                parts.push(FmtPart::Lit(str_lit));
+            }
+            TokenType::FmtStr(parts).with_pos(pos)
+        }
+    }
+
+    // the '{' has already been read, tokenizes the expression up to the matching '}' and the optional format specifier
+    fn next_interpolation(&mut self, brace_pos: Position) -> Result<FmtPart, Token> {
+        let src = self.chars.as_str();
+
+        // the expression ends at the first ':' or '}' that isn't nested in braces, parens, brackets or a string
+        // a '"' outside of those ends the enclosing string, so strings are only allowed nested
+        let mut depth = 0;
+        let mut in_str = false;
+        let mut escaped = false;
+        let mut end = None;
+        for (i, c) in src.char_indices() {
+            if in_str {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    in_str = false;
                 }
-                str_lit.push(c);
-            } else {
-                return TokenType::Error;
+                continue;
+            }
+            match c {
+                '"' if depth > 0 => in_str = true,
+                '"' => break,
+                '{' | '(' | '[' => depth += 1,
+                '}' | ')' | ']' if depth > 0 => depth -= 1,
+                '}' | ':' if depth == 0 => {
+                    end = Some(i);
+                    break;
+                },
+                _ => ()
+            }
+        }
+
+        let expr_src = match end {
+            Some(end) => &src[..end],
+            None => return Err(TokenType::Error("unterminated interpolation").with_pos(brace_pos))
+        };
+        if expr_src.trim().is_empty() {
+            return Err(TokenType::Error("expected expression").with_pos(brace_pos));
+        }
+
+        // tokens keep their position in the whole input
+        let tokens = Tokenizer { chars: expr_src.chars(), pos: self.pos.clone() }.collect();
+        for _ in expr_src.chars() {
+            self.next_char();
+        }
+
+        let spec = if self.peek_char() == Some(':') {
+            self.next_char();
+            let spec_pos = self.pos.clone();
+            let spec_src = self.chars.as_str();
+            let spec_len = spec_src.find('}').unwrap_or(spec_src.len());
+            let spec = FmtSpec::parse(&spec_src[..spec_len]);
+            for _ in spec_src[..spec_len].chars() {
+                self.next_char();
+            }
+            match spec {
+                Some(spec) => Some(spec),
+                None => return Err(TokenType::Error("invalid format specifier").with_pos(spec_pos))
+            }
+        } else {
+            None
+        };
+
+        if self.next_char() != Some('}') {
+            return Err(TokenType::Error("unterminated interpolation").with_pos(brace_pos));
+        }
+        Ok(FmtPart::Expr(tokens, spec))
+    }
+
+    // the '\' has already been read
+    fn next_escape(&mut self) -> Result<char, &'static str> {
+        match self.next_char() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some('\\') => Ok('\\'),
+            Some('"') => Ok('"'),
+            Some('\'') => Ok('\''),
+            Some('{') => Ok('{'),
+            Some('}') => Ok('}'),
+            // unicode code point, like '\u{1F600}'
+            Some('u') => {
+                if self.peek_char() != Some('{') {
+                    return Err("expected '{' after '\\u'");
+                }
+                self.next_char();
+                let mut hex = String::new();
+                while let Some(c) = self.peek_char() {
+                    if !c.is_ascii_hexdigit() || hex.len() == 6 {
+                        break;
+                    }
+                    hex.push(c);
+                    self.next_char();
+                }
+                if self.peek_char() != Some('}') {
+                    return Err("invalid unicode escape");
+                }
+                self.next_char();
+                u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).ok_or("invalid unicode escape")
+            },
+            _ => Err("unknown escape sequence")
+        }
+    }
+
+    // the 'r' has already been read, the string ends with '"' followed by as many '#' as it started with
+    fn next_raw_str(&mut self, pos: Position) -> Token {
+        let mut end = String::from("\"");
+        while self.peek_char() == Some('#') {
+            self.next_char();
+            end.push('#');
+        }
+        if self.peek_char() != Some('"') {
+            return TokenType::Error("expected '\"'").with_pos(self.pos.clone());
+        }
+        self.next_char();
+
+        let mut str_lit = String::new();
+        loop {
+            if self.chars.as_str().starts_with(&end) {
+                for _ in 0..end.len() {
+                    self.next_char();
+                }
+                return TokenType::StrLit(str_lit).with_pos(pos);
+            }
+            match self.next_char() {
+                Some(c) => str_lit.push(c),
+                None => return TokenType::Error("unterminated string").with_pos(pos)
             }
         }
-        
     }
 
-    fn next_num(&mut self, c: char) -> TokenType {
-        let len = self.chars.clone().take_while(|c| c.is_numeric()).count();
-        let mut num = String::with_capacity(len + 1);
-        num.push(c);
-        for _ in 0..len {
+    // errors are positioned on the offending character rather than on the start of the literal
+    fn next_num(&mut self, first: char, pos: Position) -> Token {
+        if first == '0' {
+            let radix = match self.peek_char() {
+                Some('x') => 16,
+                Some('o') => 8,
+                Some('b') => 2,
+                _ => 10
+            };
+            if radix != 10 {
+                self.next_char();
+                return self.next_radix_num(radix, pos);
+            }
+        }
+
+        let mut num = String::new();
+        num.push(first);
+        self.next_digits(&mut num, 10);
+
+        let mut is_float = false;
+        // only eat the '.' if a digit follows, so things like 'a.0' or ranges still work
+        if self.peek_char() == Some('.') && self.chars.clone().nth(1).is_some_and(|c| c.is_ascii_digit()) {
+            is_float = true;
             num.push(self.next_char().unwrap());
+            self.next_digits(&mut num, 10);
         }
-        TokenType::NumLit(num)
+        if let Some('e') | Some('E') = self.peek_char() {
+            is_float = true;
+            num.push(self.next_char().unwrap());
+            if let Some(sign @ '+') | Some(sign @ '-') = self.peek_char() {
+                self.next_char();
+                num.push(sign);
+            }
+            if !self.peek_char().is_some_and(|c| c.is_ascii_digit()) {
+                return TokenType::Error("expected exponent").with_pos(self.pos.clone());
+            }
+            self.next_digits(&mut num, 10);
+        }
+
+        if let Some(err) = self.check_num_end() {
+            return err;
+        }
+        if is_float {
+            match num.parse() {
+                Ok(n) => TokenType::FloatLit(n),
+                Err(_) => TokenType::Error("invalid float literal")
+            }.with_pos(pos)
+        } else {
+            match num.parse() {
+                Ok(n) => TokenType::IntLit(n),
+                Err(_) => TokenType::Error("integer literal is too large")
+            }.with_pos(pos)
+        }
+    }
+
+    // the part of '0x...', '0o...' or '0b...' following the prefix
+    fn next_radix_num(&mut self, radix: u32, pos: Position) -> Token {
+        let mut num = String::new();
+        self.next_digits(&mut num, radix);
+        if let Some(err) = self.check_num_end() {
+            return err;
+        }
+        if num.is_empty() {
+            return TokenType::Error("expected digits").with_pos(self.pos.clone());
+        }
+        match i64::from_str_radix(&num, radix) {
+            Ok(n) => TokenType::IntLit(n),
+            Err(_) => TokenType::Error("integer literal is too large")
+        }.with_pos(pos)
+    }
+
+    // pushes the digits in num, skipping '_' separators
+    fn next_digits(&mut self, num: &mut String, radix: u32) {
+        while let Some(c) = self.peek_char() {
+            if c.is_digit(radix) {
+                num.push(c);
+            } else if c != '_' {
+                break;
+            }
+            self.next_char();
+        }
+    }
+
+    // a number directly followed by something like an identifier is malformed (like '12ab' or '0b102')
+    fn check_num_end(&self) -> Option<Token> {
+        match self.peek_char() {
+            Some(c) if is_ident_char(c) => Some(TokenType::Error("invalid digit in number").with_pos(self.pos.clone())),
+            _ => None
+        }
+    }
+
+    // the '//' has already been read, returns the text of '///' doc comments ('////' is a regular comment)
+    fn next_line_comment(&mut self) -> Option<String> {
+        let len = self.chars.as_str().find('\n').unwrap_or(self.chars.as_str().len());
+        let line = &self.chars.as_str()[..len];
+        for _ in line.chars() {
+            self.next_char();
+        }
+        if line.starts_with('/') && !line.starts_with("//") {
+            let doc = &line[1..];
+            Some(String::from(doc.strip_prefix(' ').unwrap_or(doc)))
+        } else {
+            None
+        }
+    }
+
+    // the '/*' has already been read, block comments can be nested
+    fn skip_block_comment(&mut self) -> bool {
+        let mut depth = 1;
+        while depth != 0 {
+            match self.next_char() {
+                Some('/') if self.peek_char() == Some('*') => {
+                    self.next_char();
+                    depth += 1;
+                },
+                Some('*') if self.peek_char() == Some('/') => {
+                    self.next_char();
+                    depth -= 1;
+                },
+                Some(_) => (),
+                None => return false
+            }
+        }
+        true
     }
 
     fn next_ident_string(&mut self) -> &str {
-        let len = self.chars.clone().take_while(|c| c.is_alphanumeric()).count();
+        let len = self.chars.clone().take_while(|&c| is_ident_char(c)).count();
         let r = &self.chars.as_str()[..len];
          for _ in 0..len {
            self.next_char();
@@ -73,27 +370,103 @@ impl<'a> Tokenizer<'a> {
                             _ => TokenType::Not
                         },
 
-                    '+' => TokenType::Plus,
-                    '-' => TokenType::Minus,
-                    '*' => TokenType::Star,
-                    '/' => TokenType::Slash,
+                    '<' =>
+                        match self.chars.clone().next() {
+                            Some('=') => { self.next_char(); TokenType::Le },
+                            _ => TokenType::Lt
+                        },
+
+                    '>' =>
+                        match self.chars.clone().next() {
+                            Some('=') => { self.next_char(); TokenType::Ge },
+                            _ => TokenType::Gt
+                        },
+
+                    '&' =>
+                        match self.chars.clone().next() {
+                            Some('&') => { self.next_char(); TokenType::And },
+                            _ => TokenType::Error("expected '&&'")
+                        },
+
+                    '|' =>
+                        match self.chars.clone().next() {
+                            Some('|') => { self.next_char(); TokenType::Or },
+                            _ => TokenType::Error("expected '||'")
+                        },
+
+                    '+' =>
+                        match self.peek_char() {
+                            Some('=') => { self.next_char(); TokenType::PlusAssign },
+                            _ => TokenType::Plus
+                        },
+                    '-' =>
+                        match self.peek_char() {
+                            Some('=') => { self.next_char(); TokenType::MinusAssign },
+                            _ => TokenType::Minus
+                        },
+                    '*' =>
+                        match self.peek_char() {
+                            Some('=') => { self.next_char(); TokenType::StarAssign },
+                            _ => TokenType::Star
+                        },
+                    '%' => TokenType::Percent,
+                    '/' =>
+                        match self.peek_char() {
+                            Some('/') => {
+                                self.next_char();
+                                match self.next_line_comment() {
+                                    Some(doc) => TokenType::DocComment(doc),
+                                    None => continue
+                                }
+                            },
+                            Some('*') => {
+                                self.next_char();
+                                if self.skip_block_comment() {
+                                    continue;
+                                }
+                                TokenType::Error("unterminated block comment")
+                            },
+                            Some('=') => { self.next_char(); TokenType::SlashAssign },
+                            _ => TokenType::Slash
+                        },
                     ',' => TokenType::Comma,
                     ':' => TokenType::Colon,
+                    '.' =>
+                        match (self.peek_char(), self.chars.clone().nth(1)) {
+                            (Some('.'), Some('=')) => { self.next_char(); self.next_char(); TokenType::DotDotEq },
+                            (Some('.'), _) => { self.next_char(); TokenType::DotDot },
+                            _ => TokenType::Dot
+                        },
                     '(' => TokenType::LeftPar,
                     ')' => TokenType::RightPar,
                     '{' => TokenType::LeftBrace,
                     '}' => TokenType::RightBrace,
+                    '#' =>
+                        match self.chars.clone().next() {
+                            Some('{') => { self.next_char(); TokenType::HashLeftBrace },
+                            _ => TokenType::Error("expected '{' after '#'")
+                        },
                     '[' => TokenType::LeftBracket,
                     ']' => TokenType::RightBracket,
 
-                    '"' => self.next_str(),
-                    c if c.is_numeric() => self.next_num(c),
-                    c if c.is_alphabetic() => 
+                    '"' => return Some(self.next_str(token_pos)),
+                    'r' if matches!(self.peek_char(), Some('"') | Some('#')) => return Some(self.next_raw_str(token_pos)),
+                    c if c.is_ascii_digit() => return Some(self.next_num(c, token_pos)),
+                    c if c.is_alphabetic() || c == '_' => 
                         match (c, self.next_ident_string()) {
                             ('i', "f") => TokenType::If,
                             ('e', "lse") => TokenType::Else,
+                            ('m', "atch") => TokenType::Match,
                             ('l', "et") => TokenType::Let,
+                            ('f', "n") => TokenType::Fn,
                             ('w', "hile") => TokenType::While,
+                            ('f', "or") => TokenType::For,
+                            ('i', "n") => TokenType::In,
+                            ('b', "reak") => TokenType::Break,
+                            ('c', "ontinue") => TokenType::Continue,
+                            ('r', "eturn") => TokenType::Return,
+                            ('t', "rue") => TokenType::True,
+                            ('f', "alse") => TokenType::False,
                             (c, s) => {
                                 let mut name = String::with_capacity(s.len() + 1);
                                 name.push(c);
@@ -101,7 +474,16 @@ impl<'a> Tokenizer<'a> {
                                 TokenType::Ident(name)
                             }
                         },
-                    _ => TokenType::Error
+                    // loop label (like ''outer')
+                    '\'' => {
+                        let name = self.next_ident_string().to_string();
+                        if name.is_empty() {
+                            TokenType::Error("expected label name after '\''")
+                        } else {
+                            TokenType::Label(name)
+                        }
+                    },
+                    _ => TokenType::Error("unexpected character")
 
                 }.with_pos(token_pos));
             } else {
@@ -112,6 +494,10 @@ impl<'a> Tokenizer<'a> {
 
     
 
+    fn peek_char(&self) -> Option<char> {
+        self.chars.clone().next()
+    }
+
     fn next_char(&mut self) -> Option<char> {
         let c = self.chars.next();
         match c { 
@@ -132,3 +518,6 @@ impl<'a> Iterator for Tokenizer<'a> {
     }
 }
 
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
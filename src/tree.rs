@@ -6,43 +6,174 @@ use std::fmt;
 use position::*;
 use map_in_place::*;
 use typing::*;
+use format::*;
 
 type UnboxedSubTree<Name> = Tree<Name>;
 type SubTree<Name> = Box<UnboxedSubTree<Name>>;
+// pattern, optional guard and body
+pub type MatchArm<Name> = (Pattern<Name>, Option<UnboxedSubTree<Name>>, UnboxedSubTree<Name>);
+// pattern and optional default value
+pub type Param<Name> = (Pattern<Name>, Option<UnboxedSubTree<Name>>);
+// argument passed by parameter name (like 'scale: 3')
+pub type NamedArg<Name> = (Name, UnboxedSubTree<Name>);
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum TreeType<Name> {
     Empty,
 
-    Def(Name, SubTree<Name>),
+    // the doc comment preceding the definition, and whether it's a 'fn' declaration (hoisted to the start of its block)
+    Def(Name, SubTree<Name>, Option<String>, bool),
+    // destructuring definition (like 'let (a, b) = f()')
+    DefPattern(Pattern<Name>, SubTree<Name>),
     Assign(Name, SubTree<Name>),
+    // the target is an identifier or an index
+    OpAssign(AssignOp, SubTree<Name>, SubTree<Name>),
 
     Ident(Name),
 
+    BoolLit(bool),
     IntLit(i64),
+    FloatLit(f64),
     StrLit(String),
+    // interpolated string, literal parts are StrLits without format specifier
+    Format(Vec<(UnboxedSubTree<Name>, Option<FmtSpec>)>),
 
 
     Add(SubTree<Name>, SubTree<Name>),
     Sub(SubTree<Name>, SubTree<Name>),
     Mul(SubTree<Name>, SubTree<Name>),
     Div(SubTree<Name>, SubTree<Name>),
+    Mod(SubTree<Name>, SubTree<Name>),
+
+    Neg(SubTree<Name>),
+    Pos(SubTree<Name>),
+    Not(SubTree<Name>),
 
     Eq(SubTree<Name>, SubTree<Name>),
     Neq(SubTree<Name>, SubTree<Name>),
+    Lt(SubTree<Name>, SubTree<Name>),
+    Le(SubTree<Name>, SubTree<Name>),
+    Gt(SubTree<Name>, SubTree<Name>),
+    Ge(SubTree<Name>, SubTree<Name>),
+
+    And(SubTree<Name>, SubTree<Name>),
+    Or(SubTree<Name>, SubTree<Name>),
+
+    // the flag is set for inclusive ranges ('a..=b')
+    Range(SubTree<Name>, SubTree<Name>, bool),
 
-    Func(Vec<UnboxedSubTree<Name>>, Rc<UnboxedSubTree<Name>>),
-    Call(SubTree<Name>, Vec<UnboxedSubTree<Name>>),
+    // the last parameter can be a rest collecting the remaining arguments (like '(a, ..more) => more')
+    Func(Vec<Param<Name>>, Rc<UnboxedSubTree<Name>>),
+    Call(SubTree<Name>, Vec<UnboxedSubTree<Name>>, Vec<NamedArg<Name>>),
 
     Block(Vec<UnboxedSubTree<Name>>, SubTree<Name>),
     Tuple(Vec<UnboxedSubTree<Name>>),
+    List(Vec<UnboxedSubTree<Name>>),
+    Map(Vec<(UnboxedSubTree<Name>, UnboxedSubTree<Name>)>),
+
+    Index(SubTree<Name>, SubTree<Name>),
+    IndexAssign(SubTree<Name>, SubTree<Name>, SubTree<Name>),
 
     If(SubTree<Name>, SubTree<Name>, SubTree<Name>),
-    While(SubTree<Name>, SubTree<Name>),
+    // arms are tried in order, each one with an optional guard
+    Match(SubTree<Name>, Vec<MatchArm<Name>>),
+    // loops have an optional label
+    While(SubTree<Name>, SubTree<Name>, Option<Name>),
+    // the binding is an identifier or a tuple of identifiers
+    For(Box<Pattern<Name>>, SubTree<Name>, SubTree<Name>, Option<Name>),
+
+    // the label is the loop to leave, the innermost one if missing, a missing value is Empty
+    Break(Option<Name>, SubTree<Name>),
+    Continue(Option<Name>),
+    Return(SubTree<Name>),
+
+    // rest of a list in lambda parameters (like '[a, ..rest] => rest'), an error anywhere else
+    Rest(Option<Name>),
 
     Error(&'static str)
 }
 
+// operator of a compound assignment (like 'a += 1')
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AssignOp {
+    Add,
+    Sub,
+    Mul,
+    Div
+}
+
+impl fmt::Display for AssignOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AssignOp::Add => write!(f, "+="),
+            AssignOp::Sub => write!(f, "-="),
+            AssignOp::Mul => write!(f, "*="),
+            AssignOp::Div => write!(f, "/=")
+        }
+    }
+}
+
+// the shape a value is matched against, binding names along the way
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern<Name> {
+    Wildcard,
+    Bind(Name),
+    // a literal tree, compared with '=='
+    Lit(SubTree<Name>),
+    Tuple(Vec<Pattern<Name>>),
+    // may contain a single Rest
+    List(Vec<Pattern<Name>>),
+    // the remaining elements of a list (like '..' or '..tail')
+    Rest(Option<Name>)
+}
+
+impl<Name> Pattern<Name> {
+    fn for_each_ref<'a, F: FnMut(&'a Tree<Name>)>(&'a self, f: &mut F) {
+        match *self {
+            Pattern::Lit(ref lit) => lit.for_each_ref(f),
+            Pattern::Tuple(ref elems) | Pattern::List(ref elems) => for e in elems { e.for_each_ref(f) },
+            Pattern::Wildcard | Pattern::Bind(_) | Pattern::Rest(_) => ()
+        }
+    }
+}
+
+impl<Name: Clone> Pattern<Name> {
+    fn transform_ref<F: FnMut(TreeType<Name>) -> TreeType<Name>>(self, f: &mut F) -> Pattern<Name> {
+        match self {
+            Pattern::Lit(lit) => Pattern::Lit(Box::new(lit.transform_ref(f))),
+            Pattern::Tuple(elems) => Pattern::Tuple(elems.into_iter().map(|e| e.transform_ref(f)).collect()),
+            Pattern::List(elems) => Pattern::List(elems.into_iter().map(|e| e.transform_ref(f)).collect()),
+            p => p
+        }
+    }
+}
+
+impl<Name> fmt::Display for Pattern<Name> where Name: fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Bind(ref name) => write!(f, "{}", name),
+            Pattern::Lit(ref lit) => write!(f, "{}", lit),
+            Pattern::Tuple(ref elems) => {
+                write!(f, "(")?;
+                for e in elems {
+                    write!(f, "{}, ", e)?;
+                }
+                write!(f, ")")
+            },
+            Pattern::List(ref elems) => {
+                write!(f, "[")?;
+                for e in elems {
+                    write!(f, "{}, ", e)?;
+                }
+                write!(f, "]")
+            },
+            Pattern::Rest(None) => write!(f, ".."),
+            Pattern::Rest(Some(ref name)) => write!(f, "..{}", name)
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Tree<Name> {
     pub tree_type: TreeType<Name>,
@@ -117,6 +248,13 @@ impl<Name> Tree<Name> {
     pub fn is_typed(&self) -> bool {
         self.type_ref.is_typed()
     }
+
+    pub fn doc(&self) -> Option<&String> {
+        match self.tree_type {
+            TreeType::Def(_, _, Some(ref doc), _) => Some(doc),
+            _ => None
+        }
+    }
 }
 
 
@@ -164,27 +302,69 @@ impl<Name> Tree<Name> {
         macro_rules! fe { ($x:expr) => ($x.for_each_ref(f)); }
         f(self);
         match self.tree_type {
-            TreeType::Def(_, ref rhs) => fe!(rhs),
+            TreeType::Def(_, ref rhs, ..) => fe!(rhs),
+            TreeType::DefPattern(ref pattern, ref rhs) => { pattern.for_each_ref(f); fe!(rhs) },
             TreeType::Assign(_, ref rhs) => fe!(rhs),
+            TreeType::OpAssign(_, ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) },
 
             TreeType::Add(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) },
             TreeType::Sub(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) },
             TreeType::Mul(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) }, 
             TreeType::Div(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) }, 
+            TreeType::Mod(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) }, 
+
+            TreeType::Neg(ref expr) => fe!(expr), 
+            TreeType::Pos(ref expr) => fe!(expr), 
+            TreeType::Not(ref expr) => fe!(expr), 
 
             TreeType::Eq(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) }, 
             TreeType::Neq(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) }, 
+            TreeType::Lt(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) }, 
+            TreeType::Le(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) }, 
+            TreeType::Gt(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) }, 
+            TreeType::Ge(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) }, 
+
+            TreeType::And(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) }, 
+            TreeType::Or(ref lhs, ref rhs) => { fe!(lhs); fe!(rhs) }, 
 
-            TreeType::Func(ref bind, ref body) => { for b in bind { fe!(b); } fe!(body) },
-            TreeType::Call(ref func, ref args) => { fe!(func); for a in args { fe!(a); } }, 
+            TreeType::Range(ref start, ref end, _) => { fe!(start); fe!(end) }, 
+
+            TreeType::Func(ref bind, ref body) => {
+                for (b, default) in bind {
+                    b.for_each_ref(f);
+                    if let Some(default) = default { fe!(default); }
+                }
+                fe!(body)
+            },
+            TreeType::Call(ref func, ref args, ref named) => { fe!(func); for a in args { fe!(a); } for (_, a) in named { fe!(a); } }, 
 
             TreeType::Block(ref stats, ref expr) => { for s in stats { fe!(s); } fe!(expr) },
             TreeType::Tuple(ref elems) => for e in elems { fe!(e); },
+            TreeType::List(ref elems) => for e in elems { fe!(e); },
+            TreeType::Map(ref entries) => for (k, v) in entries { fe!(k); fe!(v); },
+            TreeType::Format(ref parts) => for (p, _) in parts { fe!(p); },
+
+            TreeType::Index(ref lhs, ref index) => { fe!(lhs); fe!(index) },
+            TreeType::IndexAssign(ref lhs, ref index, ref rhs) => { fe!(lhs); fe!(index); fe!(rhs) },
 
             TreeType::If(ref cond, ref thenp, ref elsep) => { fe!(cond); fe!(thenp); fe!(elsep) }, 
-            TreeType::While(ref cond, ref body) => { fe!(cond); fe!(body) }, 
+            TreeType::Match(ref expr, ref arms) => {
+                fe!(expr);
+                for (pattern, guard, body) in arms {
+                    pattern.for_each_ref(f);
+                    if let Some(guard) = guard {
+                        fe!(guard);
+                    }
+                    fe!(body);
+                }
+            },
+            TreeType::While(ref cond, ref body, _) => { fe!(cond); fe!(body) }, 
+            TreeType::For(ref bind, ref iter, ref body, _) => { bind.for_each_ref(f); fe!(iter); fe!(body) }, 
+
+            TreeType::Break(_, ref val) => fe!(val), 
+            TreeType::Return(ref val) => fe!(val), 
 
-            TreeType::Empty | TreeType::Ident(_) | TreeType::IntLit(_) | TreeType::StrLit(_) | TreeType::Error(_) => (),
+            TreeType::Empty | TreeType::Ident(_) | TreeType::BoolLit(_) | TreeType::IntLit(_) | TreeType::FloatLit(_) | TreeType::StrLit(_) | TreeType::Continue(_) | TreeType::Rest(_) | TreeType::Error(_) => (),
         }
     }
 }
@@ -199,28 +379,57 @@ impl<Name: Clone> Tree<Name> {
         macro_rules! tr { ($x:expr) => ($x.map_in_place(|t| t.transform_ref(f))); }
         let pos = self.pos.clone();
         match f(self.tree_type) {
-            TreeType::Def(name, rhs) => TreeType::Def(name, tr!(rhs)),
+            TreeType::Def(name, rhs, doc, hoisted) => TreeType::Def(name, tr!(rhs), doc, hoisted),
+            TreeType::DefPattern(pattern, rhs) => TreeType::DefPattern(pattern.transform_ref(f), tr!(rhs)),
             TreeType::Assign(name, rhs) => TreeType::Assign(name, tr!(rhs)),
+            TreeType::OpAssign(op, lhs, rhs) => TreeType::OpAssign(op, tr!(lhs), tr!(rhs)),
 
             TreeType::Add(lhs, rhs) => TreeType::Add(tr!(lhs), tr!(rhs)),
             TreeType::Sub(lhs, rhs) => TreeType::Sub(tr!(lhs), tr!(rhs)),
             TreeType::Mul(lhs, rhs) => TreeType::Mul(tr!(lhs), tr!(rhs)), 
             TreeType::Div(lhs, rhs) => TreeType::Div(tr!(lhs), tr!(rhs)), 
+            TreeType::Mod(lhs, rhs) => TreeType::Mod(tr!(lhs), tr!(rhs)), 
+
+            TreeType::Neg(expr) => TreeType::Neg(tr!(expr)), 
+            TreeType::Pos(expr) => TreeType::Pos(tr!(expr)), 
+            TreeType::Not(expr) => TreeType::Not(tr!(expr)), 
 
             TreeType::Eq(lhs, rhs) => TreeType::Eq(tr!(lhs), tr!(rhs)), 
             TreeType::Neq(lhs, rhs) => TreeType::Neq(tr!(lhs), tr!(rhs)), 
+            TreeType::Lt(lhs, rhs) => TreeType::Lt(tr!(lhs), tr!(rhs)), 
+            TreeType::Le(lhs, rhs) => TreeType::Le(tr!(lhs), tr!(rhs)), 
+            TreeType::Gt(lhs, rhs) => TreeType::Gt(tr!(lhs), tr!(rhs)), 
+            TreeType::Ge(lhs, rhs) => TreeType::Ge(tr!(lhs), tr!(rhs)), 
 
-            TreeType::Func(bind, body) => TreeType::Func(tr!(bind), tr!(body)),
-            TreeType::Call(func, args) => TreeType::Call(tr!(func), tr!(args)),
+            TreeType::And(lhs, rhs) => TreeType::And(tr!(lhs), tr!(rhs)), 
+            TreeType::Or(lhs, rhs) => TreeType::Or(tr!(lhs), tr!(rhs)), 
+
+            TreeType::Range(start, end, inclusive) => TreeType::Range(tr!(start), tr!(end), inclusive), 
+
+            TreeType::Func(bind, body) => TreeType::Func(bind.into_iter().map(|(b, default)| (b.transform_ref(f), default.map(|d| d.transform_ref(f)))).collect(), tr!(body)),
+            TreeType::Call(func, args, named) => TreeType::Call(tr!(func), tr!(args), named.into_iter().map(|(n, a)| (n, a.transform_ref(f))).collect()),
 
             TreeType::Block(stats, expr) => TreeType::Block(tr!(stats), tr!(expr)),
             TreeType::Tuple(elems) => TreeType::Tuple(tr!(elems)),
+            TreeType::List(elems) => TreeType::List(tr!(elems)),
+            TreeType::Map(entries) => TreeType::Map(entries.into_iter().map(|(k, v)| (k.transform_ref(f), v.transform_ref(f))).collect()),
+            TreeType::Format(parts) => TreeType::Format(parts.into_iter().map(|(p, spec)| (p.transform_ref(f), spec)).collect()),
+
+            TreeType::Index(lhs, index) => TreeType::Index(tr!(lhs), tr!(index)),
+            TreeType::IndexAssign(lhs, index, rhs) => TreeType::IndexAssign(tr!(lhs), tr!(index), tr!(rhs)),
 
             TreeType::If(cond, thenp, elsep) => TreeType::If(tr!(cond), tr!(thenp), tr!(elsep)), 
-            TreeType::While(cond, body) => TreeType::While(tr!(cond), tr!(body)), 
+            TreeType::Match(expr, arms) => TreeType::Match(tr!(expr), arms.into_iter()
+                .map(|(pattern, guard, body)| (pattern.transform_ref(f), guard.map(|g| g.transform_ref(f)), body.transform_ref(f)))
+                .collect()),
+            TreeType::While(cond, body, label) => TreeType::While(tr!(cond), tr!(body), label), 
+            TreeType::For(bind, iter, body, label) => TreeType::For(bind.map_in_place(|b| b.transform_ref(f)), tr!(iter), tr!(body), label), 
+
+            TreeType::Break(label, val) => TreeType::Break(label, tr!(val)), 
+            TreeType::Return(val) => TreeType::Return(tr!(val)), 
 
             
-            t @ TreeType::Empty | t @ TreeType::Ident(_) | t @ TreeType::IntLit(_) | t @ TreeType::StrLit(_) | t @ TreeType::Error(_) => t,
+            t @ TreeType::Empty | t @ TreeType::Ident(_) | t @ TreeType::BoolLit(_) | t @ TreeType::IntLit(_) | t @ TreeType::FloatLit(_) | t @ TreeType::StrLit(_) | t @ TreeType::Continue(_) | t @ TreeType::Rest(_) | t @ TreeType::Error(_) => t,
         }.with_pos(pos)
     }
 }
@@ -230,33 +439,79 @@ impl<Name> fmt::Display for Tree<Name> where Name: fmt::Display {
         match self.tree_type {
             TreeType::Empty => write!(f, "()"),
 
-            TreeType::Def(ref name, ref rhs) => write!(f, "let {} = {}", name, rhs),
+            TreeType::Def(ref name, ref rhs, ref doc, hoisted) => {
+                if let Some(ref doc) = *doc {
+                    for line in doc.lines() {
+                        writeln!(f, "/// {}", line)?;
+                    }
+                }
+                match rhs.tree_type {
+                    TreeType::Func(ref bind, ref body) if hoisted => {
+                        write!(f, "fn {}(", name)?;
+                        write_params(f, bind)?;
+                        write!(f, ") {}", body)
+                    },
+                    _ => write!(f, "let {} = {}", name, rhs)
+                }
+            },
+            TreeType::DefPattern(ref pattern, ref rhs) => write!(f, "let {} = {}", pattern, rhs),
             TreeType::Assign(ref name, ref rhs) => write!(f, "{} = {}", name, rhs),
+            TreeType::OpAssign(op, ref lhs, ref rhs) => write!(f, "{} {} {}", lhs, op, rhs),
             TreeType::Ident(ref name) => write!(f, "{}", name),
 
+            TreeType::BoolLit(val) => write!(f, "{}", val),
             TreeType::IntLit(val) => write!(f, "{}", val),
-            TreeType::StrLit(ref val) => write!(f, "\"{}\"", val),
+            TreeType::FloatLit(val) => write!(f, "{:?}", val),
+            TreeType::StrLit(ref val) => write!(f, "\"{}\"", escape_str(val)),
+            TreeType::Format(ref parts) => {
+                let mut r = write!(f, "\"");
+                for (p, spec) in parts {
+                    r = r.and_then(|_| match (&p.tree_type, spec) {
+                        (TreeType::StrLit(lit), None) => write!(f, "{}", escape_str(lit)),
+                        (_, Some(spec)) => write!(f, "{{{}:{}}}", p, spec),
+                        (_, None) => write!(f, "{{{}}}", p)
+                    });
+                }
+                r.and_then(|_| write!(f, "\""))
+            },
 
             TreeType::Add(ref lhs, ref rhs) => write!(f, "({} + {})", lhs, rhs),
             TreeType::Sub(ref lhs, ref rhs) => write!(f, "({} - {})", lhs, rhs),
-            TreeType::Mul(ref lhs, ref rhs) => write!(f, "{} * {}", lhs, rhs),
-            TreeType::Div(ref lhs, ref rhs) => write!(f, "{} / {}", lhs, rhs),
+            TreeType::Mul(ref lhs, ref rhs) => write!(f, "({} * {})", lhs, rhs),
+            TreeType::Div(ref lhs, ref rhs) => write!(f, "({} / {})", lhs, rhs),
+            TreeType::Mod(ref lhs, ref rhs) => write!(f, "({} % {})", lhs, rhs),
+
+            TreeType::Neg(ref expr) => write!(f, "(-{})", expr),
+            TreeType::Pos(ref expr) => write!(f, "(+{})", expr),
+            TreeType::Not(ref expr) => write!(f, "(!{})", expr),
+
+            TreeType::Eq(ref lhs, ref rhs) => write!(f, "({} == {})", lhs, rhs),
+            TreeType::Neq(ref lhs, ref rhs) => write!(f, "({} != {})", lhs, rhs),
+            TreeType::Lt(ref lhs, ref rhs) => write!(f, "({} < {})", lhs, rhs),
+            TreeType::Le(ref lhs, ref rhs) => write!(f, "({} <= {})", lhs, rhs),
+            TreeType::Gt(ref lhs, ref rhs) => write!(f, "({} > {})", lhs, rhs),
+            TreeType::Ge(ref lhs, ref rhs) => write!(f, "({} >= {})", lhs, rhs),
 
-            TreeType::Eq(ref lhs, ref rhs) => write!(f, "{} == {}", lhs, rhs),
-            TreeType::Neq(ref lhs, ref rhs) => write!(f, "{} != {}", lhs, rhs),
+            TreeType::And(ref lhs, ref rhs) => write!(f, "({} && {})", lhs, rhs),
+            TreeType::Or(ref lhs, ref rhs) => write!(f, "({} || {})", lhs, rhs),
 
+            TreeType::Range(ref start, ref end, false) => write!(f, "({}..{})", start, end),
+            TreeType::Range(ref start, ref end, true) => write!(f, "({}..={})", start, end),
+
+            // parenthesised so the body doesn't extend over what follows
             TreeType::Func(ref bind, ref body) => {
-                let mut r = write!(f, "(");
-                for b in bind {
-                    r = r.and_then(|_| write!(f, "{}, ", b));
-                }
-                r.and_then(|_| write!(f, ") => {}", body))
+                write!(f, "((")?;
+                write_params(f, bind)?;
+                write!(f, ") => {})", body)
             },
-            TreeType::Call(ref func, ref args) => {
+            TreeType::Call(ref func, ref args, ref named) => {
                 let mut r = write!(f, "{}(", func);
                 for a in args {
                     r = r.and_then(|_| write!(f, "{}, ", a));
                 }
+                for (name, a) in named {
+                    r = r.and_then(|_| write!(f, "{}: {}, ", name, a));
+                }
                 r.and_then(|_| write!(f, ")"))
             },
 
@@ -275,6 +530,23 @@ impl<Name> fmt::Display for Tree<Name> where Name: fmt::Display {
                 }
                 r.and_then(|_| write!(f, ")"))
             },
+            TreeType::List(ref elems) => {
+                let mut r = write!(f, "[");
+                for e in elems {
+                    r = r.and_then(|_| write!(f, "{}, ", e));
+                }
+                r.and_then(|_| write!(f, "]"))
+            },
+            TreeType::Map(ref entries) => {
+                let mut r = write!(f, "#{{");
+                for (k, v) in entries {
+                    r = r.and_then(|_| write!(f, "{}: {}, ", k, v));
+                }
+                r.and_then(|_| write!(f, "}}"))
+            },
+
+            TreeType::Index(ref lhs, ref index) => write!(f, "{}[{}]", lhs, index),
+            TreeType::IndexAssign(ref lhs, ref index, ref rhs) => write!(f, "{}[{}] = {}", lhs, index, rhs),
 
             TreeType::If(ref cond, ref thenp, ref elsep) => 
                 if elsep.is_empty() { 
@@ -282,7 +554,38 @@ impl<Name> fmt::Display for Tree<Name> where Name: fmt::Display {
                 } else {
                     write!(f, "if {} {} else {}", cond, thenp, elsep)
                 },
-            TreeType::While(ref cond, ref body) => write!(f, "while {} {}", cond, body),
+            TreeType::Match(ref expr, ref arms) => {
+                writeln!(f, "match {} {{", expr)?;
+                for (pattern, guard, body) in arms {
+                    match *guard {
+                        Some(ref guard) => writeln!(f, "{} if {} => {},", pattern, guard, body)?,
+                        None => writeln!(f, "{} => {},", pattern, body)?
+                    }
+                }
+                write!(f, "}}")
+            },
+            TreeType::While(ref cond, ref body, None) => write!(f, "while {} {}", cond, body),
+            TreeType::While(ref cond, ref body, Some(ref label)) => write!(f, "'{}: while {} {}", label, cond, body),
+            TreeType::For(ref bind, ref iter, ref body, None) => write!(f, "for {} in {} {}", bind, iter, body),
+            TreeType::For(ref bind, ref iter, ref body, Some(ref label)) => write!(f, "'{}: for {} in {} {}", label, bind, iter, body),
+
+            TreeType::Break(ref label, ref val) => {
+                write!(f, "break")?;
+                if let Some(ref label) = *label {
+                    write!(f, " '{}", label)?;
+                }
+                if !val.is_empty() {
+                    write!(f, " {}", val)?;
+                }
+                Ok(())
+            },
+            TreeType::Continue(None) => write!(f, "continue"),
+            TreeType::Continue(Some(ref label)) => write!(f, "continue '{}", label),
+            TreeType::Return(ref val) if val.is_empty() => write!(f, "return"),
+            TreeType::Return(ref val) => write!(f, "return {}", val),
+
+            TreeType::Rest(None) => write!(f, ".."),
+            TreeType::Rest(Some(ref name)) => write!(f, "..{}", name),
 
             TreeType::Error(err) => write!(f, "<error {}: {}>", self.pos, err),
 
@@ -297,6 +600,26 @@ impl<Name> fmt::Display for Tree<Name> where Name: fmt::Display {
 
 
 
+fn write_params<Name: fmt::Display>(f: &mut fmt::Formatter, params: &[Param<Name>]) -> fmt::Result {
+    for (p, default) in params {
+        match *default {
+            Some(ref default) => write!(f, "{} = {}, ", p, default)?,
+            None => write!(f, "{}, ", p)?
+        }
+    }
+    Ok(())
+}
+
+// escapes a string so it can be parsed back, braces included since they would start an interpolation
+fn escape_str(s: &str) -> String {
+    let quoted = format!("{:?}", s);
+    quoted[1..quoted.len() - 1].replace('{', "\\{").replace('}', "\\}")
+}
+
+
+
+
+
 /*impl<Name: Clone> Tree<Name> {
     pub fn rename<N, F: FnMut(TreeType<Name>) -> TreeType<N>>(self, mut f: F) -> Tree<N> {
         self.rename_ref(&mut f)
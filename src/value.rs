@@ -1,33 +1,82 @@
 
 use std::fmt::{Debug, Display, Formatter};
-use std::ops::{Add, Sub, Mul, Div, Not};
+use std::ops::{Add, Sub, Mul, Div, Rem, Neg, Not};
+use std::cmp::Ordering;
 use std::rc::Rc;
+use std::ptr;
+use std::iter;
 
 use std::fmt;
 
 use tree::*;
+use eval::Env;
+use error::*;
+use map::*;
 
 type Name = String;
 
-#[derive(Clone, PartialEq)]
+// parameters, body and captured environment of a script function
+type FuncParts = (Vec<Param<Name>>, Rc<Tree<Name>>, Env);
+
+#[derive(Clone)]
 pub enum Value { 
     Unit, 
 
+    Bool(bool),
+    Int(i64),
+    Float(f64),
     Str(String),
-    Num(f64),
 
-    Func(Vec<Name>, Rc<Tree<Name>>),
+    Func(Vec<Param<Name>>, Rc<Tree<Name>>, Env),
+    Native(Rc<NativeFunc>),
 
     Tuple(Vec<Value>),
-    List(Vec<Value>)
+    List(Vec<Value>),
+    Map(Map),
+
+    // start, end and whether the end is included
+    Range(i64, i64, bool)
+}
+
+type NativeFn = dyn Fn(&[Value]) -> Result<Value, RuntimeErrorType>;
+
+// a function implemented in Rust, errors get the position of the call that failed
+pub struct NativeFunc {
+    pub name: Name,
+    pub arity: usize,
+    func: Box<NativeFn>
+}
+
+impl NativeFunc {
+    pub fn new<F>(name: &str, arity: usize, func: F) -> NativeFunc where F: Fn(&[Value]) -> Result<Value, RuntimeErrorType> + 'static {
+        NativeFunc {
+            name: String::from(name),
+            arity,
+            func: Box::new(func)
+        }
+    }
+
+    pub fn call(&self, args: &[Value]) -> Result<Value, RuntimeErrorType> {
+        (self.func)(args)
+    }
+}
+
+// natives can not be compared, two of them are only equal if they are the same function
+impl PartialEq for NativeFunc {
+    fn eq(&self, rhs: &NativeFunc) -> bool {
+        ptr::eq(self, rhs)
+    }
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             &Value::Unit => write!(f, "Unit"),
+            &Value::Bool(b) => write!(f, "{}", b),
+            &Value::Int(v) => write!(f, "{}", v),
+            // always show a fractional part, so floats don't look like ints
+            &Value::Float(v) => write!(f, "{:?}", v),
             &Value::Str(ref s) => write!(f, "{}", s),
-            &Value::Num(v) => write!(f, "{}", v),
             &Value::List(ref lst) => {
                 let mut string = lst.iter().fold(String::new(), |s, i| s + &format!("{}", i) + ", ");
                 string.pop(); string.pop();
@@ -38,11 +87,22 @@ impl Display for Value {
                 string.pop(); string.pop();
                 write!(f, "({})", string)
             }
-            &Value::Func(ref bind, ref body) => {
-                let mut string = bind.iter().fold(String::new(), |s, i| s + &format!("{}", i) + ", ");
+            &Value::Func(ref bind, ref body, _) => {
+                let mut string = bind.iter().fold(String::new(), |s, (p, default)| match *default {
+                    Some(ref default) => s + &format!("{} = {}", p, default) + ", ",
+                    None => s + &format!("{}", p) + ", "
+                });
                 string.pop(); string.pop();
                 write!(f, "({}) => {}", string, body)
             }
+            &Value::Native(ref native) => write!(f, "<native {}>", native.name),
+            &Value::Map(ref map) => {
+                let mut string = map.iter().fold(String::new(), |s, (k, v)| s + &format!("{:?}: {}", k.to_value(), v) + ", ");
+                string.pop(); string.pop();
+                write!(f, "#{{{}}}", string)
+            }
+            &Value::Range(start, end, false) => write!(f, "{}..{}", start, end),
+            &Value::Range(start, end, true) => write!(f, "{}..={}", start, end)
         }
     }
 }
@@ -56,129 +116,275 @@ impl Debug for Value {
     }
 }
 
+// compared exactly, converting the int to a float would round it above 2^53
+fn cmp_int_float(a: i64, b: f64) -> Option<Ordering> {
+    if b.is_nan() {
+        None
+    } else if b >= 9223372036854775808.0 {
+        Some(Ordering::Less)
+    } else if b < -9223372036854775808.0 {
+        Some(Ordering::Greater)
+    } else {
+        // in range, so the integral part converts without loss
+        let int = b.trunc();
+        Some(a.cmp(&(int as i64)).then(int.partial_cmp(&b).unwrap()))
+    }
+}
+
+// ints and floats with the same value are equal, everything else is compared structurally
+impl PartialEq for Value {
+    fn eq(&self, rhs: &Value) -> bool {
+        match (self, rhs) {
+            (Value::Unit, Value::Unit) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (&Value::Int(a), &Value::Float(b)) | (&Value::Float(b), &Value::Int(a)) => cmp_int_float(a, b) == Some(Ordering::Equal),
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Func(a_bind, a_body, a_env), Value::Func(b_bind, b_body, b_env)) => a_bind == b_bind && a_body == b_body && a_env == b_env,
+            (Value::Native(a), Value::Native(b)) => a == b,
+            (Value::Tuple(a), Value::Tuple(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Map(a), Value::Map(b)) => a == b,
+            // '1..=2' is the same range as '1..3'
+            (&Value::Range(a_start, a_end, a_incl), &Value::Range(b_start, b_end, b_incl)) =>
+                a_start == b_start && a_end as i128 + a_incl as i128 == b_end as i128 + b_incl as i128,
+            _ => false
+        }
+    }
+}
+
 impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Unit => "unit",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "str",
+            Value::Func(..) | Value::Native(_) => "func",
+            Value::Tuple(_) => "tuple",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Range(..) => "range"
+        }
+    }
+
     pub fn to_str(self) -> String {
         format!("{}", self)
     }
 
-    pub fn to_list(self) -> Vec<Value> {
+    pub fn to_list(self) -> Result<Vec<Value>, RuntimeErrorType> {
+        match self {
+            Value::List(lst) => Ok(lst),
+            x => Err(type_mismatch(format!("{:?} is not a list", x)))
+        }
+    }
+
+    pub fn to_tuple(self) -> Result<Vec<Value>, RuntimeErrorType> {
+        match self {
+            Value::Tuple(lst) => Ok(lst),
+            x => Err(type_mismatch(format!("{:?} is not a tuple", x)))
+        }
+    }
+
+    pub fn to_func(self) -> Result<FuncParts, RuntimeErrorType> {
+        match self {
+            Value::Func(args, body, env) => Ok((args, body, env)),
+            x => Err(type_mismatch(format!("{:?} is not a function", x)))
+        }
+    }
+
+    pub fn to_bool(self) -> Result<bool, RuntimeErrorType> {
+        match self {
+            Value::Bool(b) => Ok(b),
+            x => Err(type_mismatch(format!("{:?} is not a bool", x)))
+        }
+    }
+
+    pub fn to_int(self) -> Result<i64, RuntimeErrorType> {
         match self {
-            Value::List(lst) => lst,
-            x => panic!("{:?} is not a list", x)
+            Value::Int(n) => Ok(n),
+            x => Err(type_mismatch(format!("{:?} is not an int", x)))
         }
     }
 
-    pub fn to_tuple(self) -> Vec<Value> {
+    // ints are promoted
+    pub fn to_float(self) -> Result<f64, RuntimeErrorType> {
         match self {
-            Value::Tuple(lst) => lst,
-            x => panic!("{:?} is not a tuple", x)
+            Value::Int(n) => Ok(n as f64),
+            Value::Float(n) => Ok(n),
+            x => Err(type_mismatch(format!("{:?} is not a number", x)))
         }
     }
 
-    pub fn to_func(self) -> (Vec<Name>, Rc<Tree<Name>>) {
+    // lists and tuples yield their elements, strings their characters, maps (key, value) tuples and ranges their ints
+    pub fn to_iter(self) -> Result<Box<dyn Iterator<Item = Value>>, RuntimeErrorType> {
         match self {
-            Value::Func(args, body) => (args, body),
-            x => panic!("{:?} is not a function", x)
+            Value::List(elems) | Value::Tuple(elems) => Ok(Box::new(elems.into_iter())),
+            Value::Str(s) => {
+                // the iterator owns the string, so it walks it by byte offset
+                let mut offset = 0;
+                Ok(Box::new(iter::from_fn(move || {
+                    let c = s[offset..].chars().next()?;
+                    offset += c.len_utf8();
+                    Some(Value::Str(c.to_string()))
+                })))
+            },
+            Value::Map(map) => Ok(Box::new(map.iter().map(|(k, v)| Value::Tuple(vec![k.to_value(), v.clone()])).collect::<Vec<_>>().into_iter())),
+            // ranges are never materialised
+            Value::Range(start, end, false) => Ok(Box::new((start..end).map(Value::Int))),
+            Value::Range(start, end, true) => Ok(Box::new((start..=end).map(Value::Int))),
+            x => Err(type_mismatch(format!("{:?} can not be iterated", x)))
         }
     }
 
-    pub fn to_bool(self) -> bool {      
-        if let Some(n) = self.try_num() {
-            n != 0.0
-        } else {
-            panic!("{:?} is not a bool", self)
+    // numbers are compared by value and strings lexicographically, None means the values are unordered (like NaNs)
+    pub fn compare(&self, rhs: &Value) -> Result<Option<Ordering>, RuntimeErrorType> {
+        match (self, rhs) {
+            (&Value::Int(a), &Value::Int(b)) => Ok(Some(a.cmp(&b))),
+            (&Value::Int(a), &Value::Float(b)) => Ok(cmp_int_float(a, b)),
+            (&Value::Float(a), &Value::Int(b)) => Ok(cmp_int_float(b, a).map(Ordering::reverse)),
+            (&Value::Float(a), &Value::Float(b)) => Ok(a.partial_cmp(&b)),
+            (Value::Str(a), Value::Str(b)) => Ok(Some(a.cmp(b))),
+            (lhs, rhs) => Err(type_mismatch(format!("{:?} and {:?} can not be compared", lhs, rhs)))
         }
     }
 
-    pub fn to_num(self) -> f64 {
-        if let Some(n) = self.try_num() {
-            n
-        } else {
-            panic!("{:?} is not a number", self)
+    pub fn index(&self, index: &Value) -> Result<Value, RuntimeErrorType> {
+        match *self {
+            Value::List(ref elems) | Value::Tuple(ref elems) => Ok(elems[to_index(index, elems.len())?].clone()),
+            Value::Map(ref map) => {
+                let key = Key::from_value(index)?;
+                map.get(&key).cloned().ok_or_else(|| RuntimeErrorType::MissingKey(format!("{:?}", index)))
+            },
+            ref x => Err(type_mismatch(format!("{:?} can not be indexed", x)))
         }
     }
 
-    fn try_num(&self) -> Option<f64> {
+    pub fn set_index(self, index: &Value, val: Value) -> Result<Value, RuntimeErrorType> {
         match self {
-            &Value::Str(ref s) => s.parse().ok(),
-            &Value::Num(n) => Some(n),
-            _ => None
+            Value::List(mut elems) => {
+                let i = to_index(index, elems.len())?;
+                elems[i] = val;
+                Ok(Value::List(elems))
+            },
+            // assigning to a missing key inserts it
+            Value::Map(mut map) => {
+                map.insert(Key::from_value(index)?, val);
+                Ok(Value::Map(map))
+            },
+            x => Err(type_mismatch(format!("{:?} can not be assigned by index", x)))
         }
     }
 }
 
 
 impl Add<Value> for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeErrorType>;
 
     fn add(self, rhs: Value) -> Self::Output {
         match self {
-            Value::Str(s) => Value::Str(s + &rhs.to_str()),
-            Value::Num(n) => Value::Num(n + rhs.to_num()),
+            Value::Str(s) => Ok(Value::Str(s + &rhs.to_str())),
             Value::List(mut v) => {
                 if let Value::List(mut r) = rhs {
                     v.append(&mut r);
                 } else {
                     v.push(rhs);
                 }
-                Value::List(v)
+                Ok(Value::List(v))
             }
 
-            _ => panic!("{:?} and {:?} can not be added", self, rhs)
+            lhs => arith(lhs, rhs, "added", i64::checked_add, |a, b| a + b)
         }
     }
 }
 
 impl Sub<Value> for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeErrorType>;
 
     fn sub(self, rhs: Value) -> Self::Output {
-        match self {
-            Value::Num(n) => Value::Num(n - rhs.to_num()),
-
-            _ => panic!("{:?} and {:?} can not be subtracted", self, rhs)
-        }
+        arith(self, rhs, "subtracted", i64::checked_sub, |a, b| a - b)
     }
 }
 
 impl Mul<Value> for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeErrorType>;
 
     fn mul(self, rhs: Value) -> Self::Output {
-        match self {
-            //Value::Str(s) => Value::Str(s * rhs.to_num()),
-            Value::Num(n) => Value::Num(n * rhs.to_num()),
-            //Value::List(mut v) => { Value::List(v * rhs.to_num()) },
-
-            _ => panic!("{:?} and {:?} can not be multiplied", self, rhs)
-        }
+        //Value::Str(s) => Value::Str(s * rhs.to_num()),
+        //Value::List(mut v) => { Value::List(v * rhs.to_num()) },
+        arith(self, rhs, "multiplied", i64::checked_mul, |a, b| a * b)
     }
 }
 
 impl Div<Value> for Value {
-    type Output = Value;
+    type Output = Result<Value, RuntimeErrorType>;
 
+    // int division truncates toward zero, float division follows IEEE 754
     fn div(self, rhs: Value) -> Self::Output {
-        match self {
-            Value::Num(n) => Value::Num(n / rhs.to_num()),
+        match (self, rhs) {
+            (Value::Int(_), Value::Int(0)) => Err(RuntimeErrorType::DivisionByZero),
+            (lhs, rhs) => arith(lhs, rhs, "divided", i64::checked_div, |a, b| a / b)
+        }
+    }
+}
 
-            _ => panic!("{:?} and {:?} can not be divided", self, rhs)
+impl Rem<Value> for Value {
+    type Output = Result<Value, RuntimeErrorType>;
+
+    // the result has the sign of the dividend
+    fn rem(self, rhs: Value) -> Self::Output {
+        match (self, rhs) {
+            (Value::Int(_), Value::Int(0)) => Err(RuntimeErrorType::DivisionByZero),
+            (lhs, rhs) => arith(lhs, rhs, "divided", i64::checked_rem, |a, b| a % b)
         }
     }
 }
 
-impl Not for Value {
-    type Output = Value;
+impl Neg for Value {
+    type Output = Result<Value, RuntimeErrorType>;
 
-    fn not(self) -> Self::Output {
-        if let Some(n) = self.try_num() {
-            Value::Num(if n == 0.0 { 1.0 } else { 0.0 })
-        } else {
-            panic!("{:?} is not a bool", self)
+    fn neg(self) -> Self::Output {
+        match self {
+            Value::Int(n) => n.checked_neg().map(Value::Int).ok_or(RuntimeErrorType::IntegerOverflow),
+            Value::Float(n) => Ok(Value::Float(-n)),
+            x => Err(type_mismatch(format!("{:?} can not be negated", x)))
         }
     }
 }
 
+impl Not for Value {
+    type Output = Result<Value, RuntimeErrorType>;
+
+    fn not(self) -> Self::Output {
+        Ok(Value::Bool(!self.to_bool()?))
+    }
+}
 
+// ints stay ints (failing on overflow) as long as both operands are ints, anything involving a float is a float
+fn arith<I, F>(lhs: Value, rhs: Value, op: &str, int_op: I, float_op: F) -> Result<Value, RuntimeErrorType> 
+    where I: Fn(i64, i64) -> Option<i64>, F: Fn(f64, f64) -> f64 {
+    match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => int_op(a, b).map(Value::Int).ok_or(RuntimeErrorType::IntegerOverflow),
+        (Value::Int(a), Value::Float(b)) => Ok(Value::Float(float_op(a as f64, b))),
+        (Value::Float(a), Value::Int(b)) => Ok(Value::Float(float_op(a, b as f64))),
+        (Value::Float(a), Value::Float(b)) => Ok(Value::Float(float_op(a, b))),
+        (lhs, rhs) => Err(type_mismatch(format!("{:?} and {:?} can not be {}", lhs, rhs, op)))
+    }
+}
 
+fn type_mismatch(msg: String) -> RuntimeErrorType {
+    RuntimeErrorType::TypeMismatch(msg)
+}
 
+// negative indices count from the end
+fn to_index(index: &Value, len: usize) -> Result<usize, RuntimeErrorType> {
+    let i = index.clone().to_int()?;
+    let resolved = if i < 0 { i + len as i64 } else { i };
+    if resolved < 0 || resolved >= len as i64 {
+        Err(RuntimeErrorType::IndexOutOfRange(i, len))
+    } else {
+        Ok(resolved as usize)
+    }
+}